@@ -0,0 +1,119 @@
+// Compares the ways of searching one large file for a literal, run with
+// `cargo bench`. The file is generated, with the query on one line in every
+// thousand. The cases:
+//  lines    splitting the contents into lines and filtering them, the way
+//           search used to work
+//  search   minigrep::search, which skips from one occurrence to the next
+//  reader   a Searcher reading the file a line at a time
+//  mapped   a Searcher on the memory mapped file, as minigrep searches
+//           large files
+// Every case runs a few times and the fastest run is printed, with the
+// throughput. MINIGREP_BENCH_MB sets the size of the file, 64 MB by default.
+
+use std::env;
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use minigrep::matcher::Substring;
+use minigrep::mmap::Mmap;
+use minigrep::sink::{Searcher, Sink};
+use minigrep::Match;
+
+const QUERY: &str = "needle";
+const RUNS: usize = 5;
+
+// Counts the matching lines, so the cases do the same work.
+struct Count(usize);
+
+impl Sink for Count {
+    fn matched(&mut self, _path: &Path, _found: &Match) -> io::Result<bool> {
+        self.0 += 1;
+        Ok(true)
+    }
+}
+
+fn main() {
+    let megabytes: usize = env::var("MINIGREP_BENCH_MB")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(64);
+    let contents = generate(megabytes * 1024 * 1024);
+    let path = env::temp_dir().join(format!("minigrep-bench-{}.txt", std::process::id()));
+    fs::write(&path, &contents).unwrap();
+    let searcher = Searcher::default();
+    let matcher = Substring::new(QUERY);
+
+    println!("searching {megabytes} MB for {QUERY:?}");
+    let expected = bench("lines", contents.len(), || {
+        contents.lines().filter(|line| line.contains(QUERY)).count()
+    });
+    let cases: [(&str, &dyn Fn() -> usize); 3] = [
+        ("search", &|| minigrep::search(QUERY, &contents).len()),
+        ("reader", &|| {
+            let reader = BufReader::with_capacity(64 * 1024, File::open(&path).unwrap());
+            let mut count = Count(0);
+            searcher
+                .search_reader(&matcher, reader, &path, &mut count)
+                .unwrap()
+        }),
+        ("mapped", &|| {
+            let map = Mmap::map(&File::open(&path).unwrap()).unwrap();
+            let mut count = Count(0);
+            searcher
+                .search_slice(&matcher, &map, &path, &mut count)
+                .unwrap()
+        }),
+    ];
+    for (name, case) in cases {
+        let found = bench(name, contents.len(), case);
+        assert_eq!(expected, found, "{name} found a different number of lines");
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+// Runs the case RUNS times, printing the fastest. Returns what it found.
+fn bench(name: &str, len: usize, case: impl Fn() -> usize) -> usize {
+    let mut fastest = Duration::MAX;
+    let mut found = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        found = black_box(case());
+        fastest = fastest.min(start.elapsed());
+    }
+    let throughput = len as f64 / fastest.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{name:>8}: {fastest:>12.2?} {throughput:>8.0} MB/s, {found} lines");
+    found
+}
+
+// Lines of words from a small vocabulary, with the query in every
+// thousandth line.
+fn generate(len: usize) -> String {
+    const WORDS: [&str; 8] = [
+        "safe",
+        "fast",
+        "productive",
+        "pick",
+        "three",
+        "duct",
+        "tape",
+        "rust",
+    ];
+    let mut contents = String::with_capacity(len + 100);
+    let mut line = 0usize;
+    while contents.len() < len {
+        for word in 0..10 {
+            contents.push_str(WORDS[(line * 7 + word * 3) % WORDS.len()]);
+            contents.push(' ');
+        }
+        if line % 1000 == 999 {
+            contents.push_str(QUERY);
+        }
+        contents.push('\n');
+        line += 1;
+    }
+    contents
+}
@@ -0,0 +1,197 @@
+// Multi-pattern substring search with the Aho-Corasick algorithm. The
+// patterns are put in a trie, which is turned into a DFA by following the
+// failure links, the longest proper suffix of a node's path that is also a
+// path in the trie. Every byte of the input then costs a single table
+// lookup, no matter how many patterns there are.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::casefold;
+
+const ROOT: usize = 0;
+
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    // The next state for every state and byte, at table[state * 256 + byte].
+    table: Vec<u32>,
+    // The lengths in bytes and characters of the patterns ending at every
+    // state, including the ones reached through failure links.
+    outputs: Vec<Vec<(usize, usize)>>,
+    // An empty pattern matches every input.
+    matches_empty: bool,
+}
+
+impl AhoCorasick {
+    pub fn new<P: AsRef<[u8]>>(patterns: &[P]) -> AhoCorasick {
+        // Build the trie, every node holding its children by byte.
+        let mut children: Vec<HashMap<u8, usize>> = vec![HashMap::new()];
+        let mut outputs: Vec<Vec<(usize, usize)>> = vec![Vec::new()];
+        let mut matches_empty = false;
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            if pattern.is_empty() {
+                matches_empty = true;
+                continue;
+            }
+
+            let mut state = ROOT;
+            for byte in pattern {
+                state = match children[state].get(byte) {
+                    Some(next) => *next,
+                    None => {
+                        children.push(HashMap::new());
+                        outputs.push(Vec::new());
+                        let next = children.len() - 1;
+                        children[state].insert(*byte, next);
+                        next
+                    }
+                };
+            }
+            let chars = String::from_utf8_lossy(pattern).chars().count();
+            outputs[state].push((pattern.len(), chars));
+        }
+
+        // Fill in the table breadth first, so the failure state of a node,
+        // which is always shallower, is complete before the node itself.
+        let mut table = vec![ROOT as u32; children.len() * 256];
+        let mut fail = vec![ROOT; children.len()];
+        let mut queue = VecDeque::new();
+
+        for (byte, child) in &children[ROOT] {
+            table[ROOT * 256 + *byte as usize] = *child as u32;
+            queue.push_back(*child);
+        }
+        while let Some(state) = queue.pop_front() {
+            let inherited = outputs[fail[state]].clone();
+            outputs[state].extend(inherited);
+
+            for byte in 0..256 {
+                let via_fail = table[fail[state] * 256 + byte];
+                table[state * 256 + byte] = match children[state].get(&(byte as u8)) {
+                    Some(child) => {
+                        fail[*child] = via_fail as usize;
+                        queue.push_back(*child);
+                        *child as u32
+                    }
+                    None => via_fail,
+                };
+            }
+        }
+
+        AhoCorasick {
+            table,
+            outputs,
+            matches_empty,
+        }
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        if self.matches_empty {
+            return true;
+        }
+
+        let mut state = ROOT;
+        for byte in haystack {
+            state = self.table[state * 256 + *byte as usize] as usize;
+            if !self.outputs[state].is_empty() {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The non-overlapping matches from left to right, preferring the longest
+    // pattern when several start at the same position.
+    pub fn find_iter(&self, haystack: &[u8]) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        let mut state = ROOT;
+        for (i, byte) in haystack.iter().enumerate() {
+            state = self.table[state * 256 + *byte as usize] as usize;
+            for (len, _) in &self.outputs[state] {
+                found.push((i + 1 - len, i + 1));
+            }
+        }
+        self.leftmost_longest(found)
+    }
+
+    // Like find_iter, but every character of the haystack is case folded as
+    // it is read, for patterns that were folded before building. Folding
+    // keeps the number of characters, so a match starts as many characters
+    // back in the haystack as the pattern has.
+    pub fn find_iter_folded(&self, haystack: &str) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        let mut state = ROOT;
+        let mut encoded = [0; 4];
+        for (offset, c) in haystack.char_indices() {
+            for byte in casefold::fold(c).encode_utf8(&mut encoded).as_bytes() {
+                state = self.table[state * 256 + *byte as usize] as usize;
+            }
+
+            let end = offset + c.len_utf8();
+            for (_, chars) in &self.outputs[state] {
+                let start = haystack[..end].char_indices().rev().nth(chars - 1);
+                found.push((start.map_or(0, |(start, _)| start), end));
+            }
+        }
+        self.leftmost_longest(found)
+    }
+
+    fn leftmost_longest(&self, mut found: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        // Sort by start and longest first, then keep what doesn't overlap.
+        found.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let mut matches: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in found {
+            if matches
+                .last()
+                .is_none_or(|(_, last_end)| start >= *last_end)
+            {
+                matches.push((start, end));
+            }
+        }
+
+        if matches.is_empty() && self.matches_empty {
+            matches.push((0, 0));
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_all_patterns() {
+        let automaton = AhoCorasick::new(&["he", "she", "his", "hers"]);
+        assert_eq!(vec![(1, 4)], automaton.find_iter(b"ushers"));
+        assert_eq!(vec![(0, 4)], automaton.find_iter(b"hers"));
+        assert_eq!(vec![(0, 3)], automaton.find_iter(b"his"));
+        assert!(automaton.is_match(b"ahe"));
+        assert!(!automaton.is_match(b"hi s"));
+    }
+
+    #[test]
+    fn prefers_longest_at_same_start() {
+        let automaton = AhoCorasick::new(&["ab", "abcd", "bc"]);
+        assert_eq!(vec![(0, 4)], automaton.find_iter(b"abcd"));
+        assert_eq!(vec![(0, 2), (3, 5)], automaton.find_iter(b"ab bc"));
+    }
+
+    #[test]
+    fn folded() {
+        let automaton = AhoCorasick::new(&["straße", "k"]);
+        assert_eq!(
+            vec![(0, 8), (9, 12)],
+            automaton.find_iter_folded("STRAẞE \u{212a}")
+        );
+    }
+
+    #[test]
+    fn empty_pattern_matches_everything() {
+        let automaton = AhoCorasick::new(&["", "x"]);
+        assert_eq!(vec![(0, 0)], automaton.find_iter(b"abc"));
+        assert_eq!(vec![(1, 2)], automaton.find_iter(b"ax"));
+        assert!(automaton.is_match(b""));
+    }
+}
@@ -0,0 +1,388 @@
+// Command-line parsing. Parser splits the arguments into options and plain
+// values, handling the usual forms:
+//  -i -n        short flags
+//  -in          several short flags combined
+//  -A 3, -A3    a short option with its value
+//  --count      long flags
+//  --context 3, --context=3
+//  --           ends the options, everything after it is a value
+// A single "-" is a value, it stands for standard input.
+
+use std::env;
+use std::fmt;
+use std::fs;
+
+use crate::regex;
+
+// The environment variable naming the config file.
+pub const CONFIG_ENV: &str = "MINIGREP_CONFIG_PATH";
+
+pub const HELP: &str = "\
+Search for QUERY in each PATH, or standard input when no PATH is given.
+Directories are searched recursively.
+
+Default options can be put in a file named by MINIGREP_CONFIG_PATH, one
+argument per line. Lines starting with '#' are comments. The options on the
+command line come after them, so they take precedence.
+
+`minigrep index` builds a trigram index of each directory, the current one
+by default, in a .minigrep-index file. Running it again only reads the files
+that changed. Searching with --index only reads the files the index says may
+match, or every file when the directory changed since it was indexed. To
+search for the word index, give it with -e.
+
+Usage: minigrep [OPTIONS] QUERY [PATH ...]
+       minigrep [OPTIONS] -e QUERY ... [PATH ...]
+       minigrep [OPTIONS] -f FILE ... [PATH ...]
+       minigrep index [OPTIONS] [DIR ...]
+
+Options:
+  -e, --regexp QUERY           Search for QUERY, can be given several times
+  -f, --file FILE              Search for every line of FILE, can be given several times
+  -E, --regex                  Treat QUERY as a regular expression
+      --fuzzy NUM              Match text within NUM insertions, deletions or
+                               substitutions of QUERY, printing the distance as d=NUM
+      --replace TEXT           Print the lines with every match replaced by TEXT,
+                               where $1 or ${1} is a group of a regular expression
+      --diff                   Print the changes --replace makes as a unified diff
+      --in-place               Write the changes --replace makes back to the files
+  -a, --text                   Search binary files as text instead of noting a match
+  -z, --search-zip             Search the contents of gzip, xz, bzip2 and zstd files
+  -i, --ignore-case            Search case insensitively, also enabled by IGNORE_CASE
+  -s, --case-sensitive         Search case sensitively, even when IGNORE_CASE is set
+  -S, --smart-case             Search case insensitively unless QUERY has uppercase letters
+  -U, --multiline              Let matches span lines, printing every line they cover;
+                               \n in a regular expression matches the line break
+  -w, --word-regexp            Only match whole words
+  -x, --line-regexp            Only match whole lines
+  -v, --invert-match           Select the lines that do not match
+  -m, --max-count NUM          Stop reading an input after NUM matching lines
+  -c, --count                  Print the number of matching lines of every input
+  -l, --files-with-matches     Print only the paths of inputs with matches
+  -L, --files-without-match    Print only the paths of inputs without matches
+  -q, --quiet                  Print nothing, exit with status 0 on any match
+  -n, --line-number            Print line numbers
+      --column                 Print the column of the first match
+  -b, --byte-offset            Print the byte offset of every line
+      --vimgrep                Print every match as path:line:column:text
+      --json                   Print every match and context line as a JSON object
+  -A, --after-context NUM      Print NUM lines after every match
+  -B, --before-context NUM     Print NUM lines before every match
+  -C, --context NUM            Print NUM lines before and after every match
+      --color WHEN             Colour the output: auto, always or never
+      --colors SPEC            Colours as GREP_COLORS style pairs, e.g. mt=1;32:fn=34
+  -r, --recursive              Search directories recursively, always enabled
+      --max-depth NUM          Descend at most NUM directories below each PATH
+      --follow                 Follow symbolic links
+      --hidden                 Search hidden files and directories
+      --no-ignore              Don't skip paths matched by .gitignore and .ignore files
+  -t, --type TYPE              Only search files of TYPE, such as rust or toml
+  -T, --type-not TYPE          Don't search files of TYPE
+      --type-add NAME:GLOB     Add GLOB to the file type NAME, new or built in
+  -g, --glob GLOB              Only search paths matching GLOB, or skip them with !GLOB
+  -j, --threads NUM            Search with NUM threads, 0 for one per CPU core (default)
+      --sort SORTBY            Print results in path order with \"path\", or \"none\" (default)
+      --index                  Use the index of each directory to skip files that can't match
+      --no-config              Don't read the config file
+  -h, --help                   Print this help
+  -V, --version                Print the version
+
+Exit status is 0 if a line matched, 1 if not and 2 if an error occurred.
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    UnknownOption(String),
+    MissingValue(String),
+    // A value given to a flag with --flag=value.
+    UnexpectedValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+        expected: String,
+    },
+    MissingQuery,
+    // A file given to -f could not be read.
+    PatternFile {
+        path: String,
+        message: String,
+    },
+    // The file named by MINIGREP_CONFIG_PATH could not be read.
+    ConfigFile {
+        path: String,
+        message: String,
+    },
+    InvalidPattern(regex::Error),
+    // An option that only makes sense together with another one.
+    Requires {
+        option: String,
+        requires: &'static str,
+    },
+    Conflict {
+        option: String,
+        other: String,
+    },
+    // Not errors, but they stop parsing like one. The caller prints the help
+    // text or the version.
+    Help,
+    Version,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::UnknownOption(option) => write!(f, "unknown option '{option}'"),
+            ArgsError::MissingValue(option) => write!(f, "option '{option}' requires a value"),
+            ArgsError::UnexpectedValue(option) => {
+                write!(f, "option '{option}' does not take a value")
+            }
+            ArgsError::InvalidValue {
+                option,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value '{value}' for option '{option}': expected {expected}"
+            ),
+            ArgsError::MissingQuery => write!(f, "Didn't get a query string"),
+            ArgsError::PatternFile { path, message } => write!(f, "{path}: {message}"),
+            ArgsError::ConfigFile { path, message } => {
+                write!(f, "config file {path}: {message}")
+            }
+            ArgsError::InvalidPattern(err) => write!(f, "{err}"),
+            ArgsError::Requires { option, requires } => {
+                write!(f, "option '{option}' requires {requires}")
+            }
+            ArgsError::Conflict { option, other } => {
+                write!(f, "option '{option}' can't be used with '{other}'")
+            }
+            ArgsError::Help => write!(f, "{}", HELP.trim_end()),
+            ArgsError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+// The arguments in the config file named by MINIGREP_CONFIG_PATH, none when
+// it isn't set.
+pub fn config_file_args() -> Result<Vec<String>, ArgsError> {
+    let path = match env::var_os(CONFIG_ENV) {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(Vec::new()),
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(parse_config(&contents)),
+        Err(err) => Err(ArgsError::ConfigFile {
+            path: path.to_string_lossy().into_owned(),
+            message: err.to_string(),
+        }),
+    }
+}
+
+// Every line is one argument, kept as it is so patterns may hold spaces.
+// Blank lines and comments, lines starting with '#' after any whitespace,
+// are skipped.
+pub fn parse_config(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| {
+            let trimmed = line.trim_start();
+            !trimmed.is_empty() && !trimmed.starts_with('#')
+        })
+        .map(String::from)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    // The option as written, "-i" or "--ignore-case", without any value.
+    Option(String),
+    Value(String),
+}
+
+pub struct Parser<I: Iterator<Item = String>> {
+    args: I,
+    // Short flags still to return from a combined group like -inr.
+    shorts: Vec<char>,
+    // The value given with --option=value.
+    inline: Option<String>,
+    // The option last returned, for error messages.
+    last: String,
+    // Set after "--", when everything is a value.
+    only_values: bool,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    pub fn new(args: I) -> Parser<I> {
+        Parser {
+            args,
+            shorts: Vec::new(),
+            inline: None,
+            last: String::new(),
+            only_values: false,
+        }
+    }
+
+    pub fn next_arg(&mut self) -> Result<Option<Arg>, ArgsError> {
+        // An inline value that no one asked for was given to a flag.
+        if self.inline.is_some() {
+            return Err(ArgsError::UnexpectedValue(self.last.clone()));
+        }
+
+        if !self.shorts.is_empty() {
+            let short = self.shorts.remove(0);
+            return Ok(Some(self.option(format!("-{short}"))));
+        }
+
+        let arg = match self.args.next() {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+        if self.only_values || arg == "-" || !arg.starts_with('-') {
+            return Ok(Some(Arg::Value(arg)));
+        }
+        if arg == "--" {
+            self.only_values = true;
+            return self.next_arg();
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            return match long.split_once('=') {
+                Some((name, value)) => {
+                    self.inline = Some(value.to_string());
+                    Ok(Some(self.option(format!("--{name}"))))
+                }
+                None => Ok(Some(self.option(arg))),
+            };
+        }
+
+        self.shorts = arg.chars().skip(1).collect();
+        self.next_arg()
+    }
+
+    fn option(&mut self, option: String) -> Arg {
+        self.last = option.clone();
+        Arg::Option(option)
+    }
+
+    // The value of the option last returned by next. It is the rest of a
+    // short group, as in -A3, the part after '=' or else the next argument.
+    pub fn value(&mut self) -> Result<String, ArgsError> {
+        if let Some(value) = self.inline.take() {
+            return Ok(value);
+        }
+        if !self.shorts.is_empty() {
+            return Ok(self.shorts.drain(..).collect());
+        }
+        self.args
+            .next()
+            .ok_or_else(|| ArgsError::MissingValue(self.last.clone()))
+    }
+
+    pub fn number(&mut self) -> Result<usize, ArgsError> {
+        let value = self.value()?;
+        value.parse().map_err(|_| self.invalid(value, "a number"))
+    }
+
+    pub fn invalid(&self, value: String, expected: &str) -> ArgsError {
+        ArgsError::InvalidValue {
+            option: self.last.clone(),
+            value,
+            expected: expected.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Parser<impl Iterator<Item = String>> {
+        Parser::new(
+            args.iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+                .into_iter(),
+        )
+    }
+
+    fn option(name: &str) -> Option<Arg> {
+        Some(Arg::Option(name.to_string()))
+    }
+
+    fn value(value: &str) -> Option<Arg> {
+        Some(Arg::Value(value.to_string()))
+    }
+
+    #[test]
+    fn options_and_values() {
+        let mut parser = parse(&["-in", "query", "--count", "-", "--", "-v", "--x"]);
+        assert_eq!(option("-i"), parser.next_arg().unwrap());
+        assert_eq!(option("-n"), parser.next_arg().unwrap());
+        assert_eq!(value("query"), parser.next_arg().unwrap());
+        assert_eq!(option("--count"), parser.next_arg().unwrap());
+        assert_eq!(value("-"), parser.next_arg().unwrap());
+        assert_eq!(value("-v"), parser.next_arg().unwrap());
+        assert_eq!(value("--x"), parser.next_arg().unwrap());
+        assert_eq!(None, parser.next_arg().unwrap());
+    }
+
+    #[test]
+    fn option_values() {
+        let mut parser = parse(&["-A3", "-B", "2", "--context=1", "--max-depth", "4", "-m"]);
+        for expected in ["3", "2", "1", "4"] {
+            parser.next_arg().unwrap();
+            assert_eq!(expected, parser.value().unwrap());
+        }
+
+        assert_eq!(option("-m"), parser.next_arg().unwrap());
+        assert_eq!(
+            ArgsError::MissingValue(String::from("-m")),
+            parser.value().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn config_lines() {
+        let contents = "\
+# Defaults for minigrep
+--smart-case
+
+  # indented comment
+--glob=!target
+--colors
+mt=1;32\r
+-e
+two words
+";
+        assert_eq!(
+            vec![
+                "--smart-case",
+                "--glob=!target",
+                "--colors",
+                "mt=1;32",
+                "-e",
+                "two words"
+            ],
+            parse_config(contents)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut parser = parse(&["--hidden=yes"]);
+        parser.next_arg().unwrap();
+        assert_eq!(
+            "option '--hidden' does not take a value",
+            parser.next_arg().unwrap_err().to_string()
+        );
+
+        let mut parser = parse(&["-C", "many"]);
+        parser.next_arg().unwrap();
+        assert_eq!(
+            "invalid value 'many' for option '-C': expected a number",
+            parser.number().unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// Unicode simple case folding, used for case-insensitive search. Two strings
+// match case-insensitively when their characters fold to the same ones.
+// Simple folding maps every character to exactly one character, unlike
+// lowercasing, which turns 'İ' into "i̇", two characters. That keeps the
+// byte offsets of a match easy to find and means a line never has to be
+// copied to search it.
+//
+// As the folding is simple, 'ß' matches 'ẞ' but not "SS", and the Turkish
+// dotted 'İ' and dotless 'ı' only match themselves, as in CaseFolding.txt
+// without the Turkic mappings.
+
+// Characters whose folding is not their single character lowercase form,
+// from the C and S mappings of CaseFolding.txt.
+const SPECIAL: [(char, char); 22] = [
+    ('\u{b5}', '\u{3bc}'),    // µ MICRO SIGN
+    ('\u{17f}', 's'),         // ſ LATIN SMALL LETTER LONG S
+    ('\u{345}', '\u{3b9}'),   // COMBINING GREEK YPOGEGRAMMENI
+    ('\u{3c2}', '\u{3c3}'),   // ς GREEK SMALL LETTER FINAL SIGMA
+    ('\u{3d0}', '\u{3b2}'),   // ϐ GREEK BETA SYMBOL
+    ('\u{3d1}', '\u{3b8}'),   // ϑ GREEK THETA SYMBOL
+    ('\u{3d5}', '\u{3c6}'),   // ϕ GREEK PHI SYMBOL
+    ('\u{3d6}', '\u{3c0}'),   // ϖ GREEK PI SYMBOL
+    ('\u{3f0}', '\u{3ba}'),   // ϰ GREEK KAPPA SYMBOL
+    ('\u{3f1}', '\u{3c1}'),   // ϱ GREEK RHO SYMBOL
+    ('\u{3f5}', '\u{3b5}'),   // ϵ GREEK LUNATE EPSILON SYMBOL
+    ('\u{1c80}', '\u{432}'),  // ᲀ CYRILLIC SMALL LETTER ROUNDED VE
+    ('\u{1c81}', '\u{434}'),  // ᲁ CYRILLIC SMALL LETTER LONG-LEGGED DE
+    ('\u{1c82}', '\u{43e}'),  // ᲂ CYRILLIC SMALL LETTER NARROW O
+    ('\u{1c83}', '\u{441}'),  // ᲃ CYRILLIC SMALL LETTER WIDE ES
+    ('\u{1c84}', '\u{442}'),  // ᲄ CYRILLIC SMALL LETTER TALL TE
+    ('\u{1c85}', '\u{442}'),  // ᲅ CYRILLIC SMALL LETTER THREE-LEGGED TE
+    ('\u{1c86}', '\u{44a}'),  // ᲆ CYRILLIC SMALL LETTER TALL HARD SIGN
+    ('\u{1c87}', '\u{463}'),  // ᲇ CYRILLIC SMALL LETTER TALL YAT
+    ('\u{1c88}', '\u{a64b}'), // ᲈ CYRILLIC SMALL LETTER UNBLENDED UK
+    ('\u{1e9b}', '\u{1e61}'), // ẛ LATIN SMALL LETTER LONG S WITH DOT ABOVE
+    ('\u{1fbe}', '\u{3b9}'),  // ι GREEK PROSGEGRAMMENI
+];
+
+pub fn fold(c: char) -> char {
+    // Most text is ASCII, which needs no lookups.
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    if let Ok(index) = SPECIAL.binary_search_by_key(&c, |(from, _)| *from) {
+        return SPECIAL[index].1;
+    }
+    // Cherokee is the one script that folds to uppercase.
+    match c {
+        '\u{13a0}'..='\u{13f5}' => return c,
+        '\u{13f8}'..='\u{13fd}' => return char::from_u32(c as u32 - 8).unwrap_or(c),
+        '\u{ab70}'..='\u{abbf}' => return char::from_u32(c as u32 - 0x97d0).unwrap_or(c),
+        _ => {}
+    }
+
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        // Lowercase forms longer than one character only have full foldings.
+        _ => c,
+    }
+}
+
+// The non-overlapping matches of the folded query in line, from left to
+// right. Each match spans as many characters of line as the query has.
+pub fn find_iter(folded_query: &[char], line: &str) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start <= line.len() {
+        match match_at(folded_query, &line[start..]) {
+            Some(len) if len > 0 => {
+                matches.push((start, start + len));
+                start += len;
+                continue;
+            }
+            // Empty matches step over a character so the search moves on.
+            Some(_) => matches.push((start, start)),
+            None => {}
+        }
+        match line[start..].chars().next() {
+            Some(c) => start += c.len_utf8(),
+            None => break,
+        }
+    }
+    matches
+}
+
+// The length in bytes of the match of the folded query at the start of text.
+fn match_at(folded_query: &[char], text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    for expected in folded_query {
+        match chars.next() {
+            Some((_, c)) if fold(c) == *expected => {}
+            _ => return None,
+        }
+    }
+    Some(chars.next().map_or(text.len(), |(offset, _)| offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(query: &str, line: &str) -> Vec<(usize, usize)> {
+        let query: Vec<char> = query.chars().map(fold).collect();
+        find_iter(&query, line)
+    }
+
+    #[test]
+    fn folding() {
+        assert_eq!('a', fold('A'));
+        assert_eq!('ß', fold('ẞ'));
+        assert_eq!('σ', fold('ς'));
+        assert_eq!('σ', fold('Σ'));
+        assert_eq!('s', fold('ſ'));
+        assert_eq!('k', fold('\u{212a}'));
+        assert_eq!('İ', fold('İ'));
+        assert_eq!('ı', fold('ı'));
+        assert_eq!('\u{13a0}', fold('\u{ab70}'));
+        assert_eq!('в', fold('\u{1c80}'));
+        assert_eq!('т', fold('\u{1c85}'));
+        assert_eq!('ꙋ', fold('\u{1c88}'));
+        assert_eq!('ꙋ', fold('Ꙋ'));
+        assert!(SPECIAL.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn find_folded() {
+        assert_eq!(vec![(0, 4), (9, 13)], find("rust", "RUST and rUsT"));
+        assert_eq!(vec![(8, 15)], find("straße", "Große, STRAßE"));
+        assert_eq!(vec![(0, 4)], find("SSS", "ſsS"));
+        assert_eq!(Vec::<(usize, usize)>::new(), find("strasse", "STRAßE"));
+        assert_eq!(vec![(0, 0), (1, 1), (3, 3)], find("", "aé"));
+        assert_eq!(vec![(0, 6)], find("ΣΑΣ", "σας"));
+        assert_eq!(vec![(0, 5)], find("ВЕ", "\u{1c80}е"));
+    }
+}
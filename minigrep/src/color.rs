@@ -0,0 +1,117 @@
+// ANSI colours for the output. A colour scheme maps every part of the output
+// to an SGR code, the numbers between "\x1b[" and "m" in an escape sequence,
+// such as "1;31" for bold red.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    // Colour only when writing to a terminal and NO_COLOR is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org asks for any non-empty value to disable colours.
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+// The defaults match the ones used by GNU grep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorScheme {
+    pub matched: String,
+    pub path: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    pub separator: String,
+}
+
+impl Default for ColorScheme {
+    fn default() -> ColorScheme {
+        ColorScheme {
+            matched: String::from("1;31"),
+            path: String::from("35"),
+            line_number: String::from("32"),
+            byte_offset: String::from("32"),
+            separator: String::from("36"),
+        }
+    }
+}
+
+impl ColorScheme {
+    // Parses a GREP_COLORS style list of capabilities on top of the defaults,
+    // for example "mt=1;32:fn=34". The capabilities are mt (match), fn (file
+    // name), ln (line number), bn (byte offset) and se (separator).
+    pub fn parse(spec: &str) -> Result<ColorScheme, &'static str> {
+        let mut scheme = ColorScheme::default();
+        for capability in spec.split(':').filter(|capability| !capability.is_empty()) {
+            let (name, code) = match capability.split_once('=') {
+                Some(pair) => pair,
+                None => return Err("--colors expects name=code pairs separated by ':'"),
+            };
+            if !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return Err("--colors codes may only hold digits and ';'");
+            }
+
+            let field = match name {
+                "mt" => &mut scheme.matched,
+                "fn" => &mut scheme.path,
+                "ln" => &mut scheme.line_number,
+                "bn" => &mut scheme.byte_offset,
+                "se" => &mut scheme.separator,
+                _ => return Err("--colors names must be one of mt, fn, ln, bn or se"),
+            };
+            *field = code.to_string();
+        }
+        Ok(scheme)
+    }
+}
+
+// Wraps text in the escape sequences that colour it and reset it afterwards.
+pub fn paint(code: &str, text: &str) -> String {
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_choice() {
+        assert_eq!(Some(ColorChoice::Always), ColorChoice::parse("always"));
+        assert_eq!(None, ColorChoice::parse("sometimes"));
+        assert!(!ColorChoice::Never.enabled());
+    }
+
+    #[test]
+    fn parse_scheme() {
+        let scheme = ColorScheme::parse("mt=1;32:fn=34").unwrap();
+        assert_eq!("1;32", scheme.matched);
+        assert_eq!("34", scheme.path);
+        assert_eq!("32", scheme.line_number);
+
+        assert!(ColorScheme::parse("mt").is_err());
+        assert!(ColorScheme::parse("xx=1").is_err());
+        assert!(ColorScheme::parse("mt=red").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// Searching compressed files, for --search-zip. The format is recognised by
+// the magic bytes at the start of the file, not by its extension, and the
+// file is decompressed by running the usual command line tool for it, which
+// writes the contents to a pipe the search reads from.
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+}
+
+impl Format {
+    // The format of the data starting with header, None if it isn't compressed.
+    pub fn detect(header: &[u8]) -> Option<Format> {
+        const MAGIC: [(&[u8], Format); 4] = [
+            (&[0x1f, 0x8b], Format::Gzip),
+            (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Format::Xz),
+            (b"BZh", Format::Bzip2),
+            (&[0x28, 0xb5, 0x2f, 0xfd], Format::Zstd),
+        ];
+        MAGIC
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, format)| *format)
+    }
+
+    // The program that decompresses the format. All of them take -d to
+    // decompress and -c to write to stdout.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Xz => "xz",
+            Format::Bzip2 => "bzip2",
+            Format::Zstd => "zstd",
+        }
+    }
+}
+
+// A running decompressor. What it prints to stderr is read on a thread of
+// its own while the search reads its stdout, otherwise a child printing
+// more warnings than a pipe holds would block, and the search with it.
+pub struct Decompressor {
+    format: Format,
+    child: Child,
+    stderr: JoinHandle<String>,
+}
+
+// Starts decompressing the file, its contents can be read from the stdout
+// of the child.
+pub fn spawn(format: Format, path: &Path) -> io::Result<Decompressor> {
+    let mut child = Command::new(format.program())
+        .args(["-d", "-c", "--"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            let message = format!("failed to run {}: {err}", format.program());
+            io::Error::new(err.kind(), message)
+        })?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut message = Vec::new();
+        let _ = stderr.read_to_end(&mut message);
+        String::from_utf8_lossy(&message).into_owned()
+    });
+    Ok(Decompressor {
+        format,
+        child,
+        stderr,
+    })
+}
+
+impl Decompressor {
+    pub fn stdout(&mut self) -> ChildStdout {
+        self.child.stdout.take().expect("stdout is piped")
+    }
+
+    // Waits for the child to exit, turning a failure into an error with the
+    // message it printed. The stdout of the child must be dropped before, so
+    // a child whose output wasn't read to the end is stopped by the closed
+    // pipe.
+    pub fn finish(mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        // The thread ends with the child, which closes the pipe.
+        let message = self.stderr.join().unwrap_or_default();
+
+        // Being killed by a signal has no exit code, that is the closed pipe
+        // after the search stopped early.
+        match status.code() {
+            Some(code) if code != 0 => {
+                let message = match message.trim() {
+                    "" => format!("{} exited with status {code}", self.format.program()),
+                    message => message.to_string(),
+                };
+                Err(io::Error::other(message))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_bytes() {
+        assert_eq!(Some(Format::Gzip), Format::detect(&[0x1f, 0x8b, 8, 0]));
+        assert_eq!(Some(Format::Xz), Format::detect(b"\xfd7zXZ\x00\x00\x04"));
+        assert_eq!(Some(Format::Bzip2), Format::detect(b"BZh91AY"));
+        assert_eq!(
+            Some(Format::Zstd),
+            Format::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x04])
+        );
+        assert_eq!(None, Format::detect(b"plain text"));
+        assert_eq!(None, Format::detect(&[0x1f]));
+    }
+}
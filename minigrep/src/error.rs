@@ -0,0 +1,115 @@
+// The errors a search can fail with. Errors reading one of many inputs don't
+// stop the search: they are printed as they happen, the other inputs are
+// still searched, and the search fails at the end with Error::Inputs.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::args::ArgsError;
+
+#[derive(Debug)]
+pub enum Error {
+    // The command line or the config file is wrong.
+    Args(ArgsError),
+    // A path that doesn't exist.
+    NotFound(PathBuf),
+    // A path that can't be read, or written with --in-place.
+    PermissionDenied(PathBuf),
+    // Any other error reading or writing a path.
+    Io { path: PathBuf, source: io::Error },
+    // The results couldn't be written, which ends the search.
+    Output(io::Error),
+    // The search went on after errors with this many inputs.
+    Inputs(usize),
+}
+
+impl Error {
+    // The error for an io::Error with the path it happened on. An error
+    // carrying a message of its own, like a decompressor that couldn't be
+    // run, keeps it instead of being reported as the path's.
+    pub fn io(path: &Path, err: io::Error) -> Error {
+        let path = path.to_path_buf();
+        if err.get_ref().is_some() {
+            return Error::Io { path, source: err };
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(path),
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(path),
+            _ => Error::Io { path, source: err },
+        }
+    }
+
+    // The path the error is about, if it is about one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::NotFound(path) | Error::PermissionDenied(path) | Error::Io { path, .. } => {
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Args(err) => write!(f, "{err}"),
+            Error::NotFound(path) => write!(f, "{}: No such file or directory", path.display()),
+            Error::PermissionDenied(path) => write!(f, "{}: Permission denied", path.display()),
+            Error::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Output(err) => write!(f, "writing the results: {err}"),
+            Error::Inputs(1) => write!(f, "1 input could not be searched"),
+            Error::Inputs(count) => write!(f, "{count} inputs could not be searched"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Args(err) => Some(err),
+            Error::Io { source, .. } | Error::Output(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ArgsError> for Error {
+    fn from(err: ArgsError) -> Error {
+        Error::Args(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_and_messages() {
+        let path = Path::new("poem.txt");
+        let not_found = Error::io(path, io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(not_found, Error::NotFound(_)));
+        assert_eq!("poem.txt: No such file or directory", not_found.to_string());
+
+        let denied = Error::io(path, io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(Some(path), denied.path());
+
+        let gzip = io::Error::new(io::ErrorKind::NotFound, "failed to run gzip");
+        assert_eq!(
+            "poem.txt: failed to run gzip",
+            Error::io(path, gzip).to_string()
+        );
+
+        let other = Error::io(path, io::Error::other("disk on fire"));
+        assert_eq!("poem.txt: disk on fire", other.to_string());
+        assert!(std::error::Error::source(&other).is_some());
+
+        assert_eq!(
+            "2 inputs could not be searched",
+            Error::Inputs(2).to_string()
+        );
+        let args = Error::from(ArgsError::MissingQuery);
+        assert_eq!(None, args.path());
+    }
+}
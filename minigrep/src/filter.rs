@@ -0,0 +1,216 @@
+// Choosing which files to search by name, with file types and globs.
+//
+// A file type is a name for a set of globs, like rust for *.rs. With --type
+// only files of the given types are searched, and --type-not skips the files
+// of a type. Globs given with --glob are matched like the lines of a
+// .gitignore file, so one without a '/' matches the name at any depth, and
+// the last glob that matches decides: a plain glob selects the path and one
+// starting with '!' skips it. When there are any plain globs, files that
+// match none of them are skipped. Directories are only ever skipped by
+// '!' globs.
+
+use std::collections::BTreeMap;
+
+use crate::glob::Glob;
+
+// The built-in file types, in the form --type-add takes.
+const TYPES: [(&str, &[&str]); 24] = [
+    ("c", &["*.c", "*.h"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx"]),
+    ("csharp", &["*.cs"]),
+    ("css", &["*.css", "*.scss"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json"]),
+    ("kotlin", &["*.kt", "*.kts"]),
+    ("lock", &["*.lock", "package-lock.json"]),
+    ("make", &["Makefile", "makefile", "*.mk"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("ruby", &["*.rb", "Gemfile", "Rakefile"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("sql", &["*.sql"]),
+    ("swift", &["*.swift"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx", "*.mts", "*.cts"]),
+    ("txt", &["*.txt"]),
+    ("xml", &["*.xml"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+// The file types known to a search, the built-in ones and those added with
+// --type-add.
+#[derive(Debug, Clone)]
+pub struct Types {
+    globs: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Types {
+    fn default() -> Types {
+        let globs = TYPES
+            .iter()
+            .map(|(name, globs)| {
+                let globs = globs.iter().map(|glob| glob.to_string()).collect();
+                (name.to_string(), globs)
+            })
+            .collect();
+        Types { globs }
+    }
+}
+
+impl Types {
+    // Adds a glob to a type, new or existing, from a "name:glob" definition.
+    pub fn add(&mut self, definition: &str) -> Result<(), &'static str> {
+        match definition.split_once(':') {
+            Some((name, glob)) if !name.is_empty() && !glob.is_empty() => {
+                let globs = self.globs.entry(name.to_string()).or_default();
+                globs.push(glob.to_string());
+                Ok(())
+            }
+            _ => Err("a type definition like name:*.ext"),
+        }
+    }
+
+    // The globs of a type, matching file names. None for unknown types.
+    fn globs(&self, name: &str) -> Option<Vec<Glob>> {
+        let globs = self.globs.get(name)?;
+        Some(globs.iter().map(|glob| Glob::new(glob)).collect())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    // The file name globs of the types given with --type and --type-not.
+    types: Vec<Glob>,
+    types_not: Vec<Glob>,
+    // The --glob patterns in order, with whether they start with '!'.
+    globs: Vec<(Glob, bool)>,
+    // Whether any glob selects paths, so files must match one.
+    has_includes: bool,
+}
+
+impl FileFilter {
+    // Builds the filter for the selected and negated type names, failing
+    // with the name of an unknown type.
+    pub fn new(
+        types: &Types,
+        selected: &[String],
+        negated: &[String],
+        globs: &[String],
+    ) -> Result<FileFilter, String> {
+        let resolve = |names: &[String]| -> Result<Vec<Glob>, String> {
+            let mut resolved = Vec::new();
+            for name in names {
+                resolved.extend(types.globs(name).ok_or_else(|| name.clone())?);
+            }
+            Ok(resolved)
+        };
+
+        let globs: Vec<(Glob, bool)> = globs.iter().map(|glob| parse_glob(glob)).collect();
+        Ok(FileFilter {
+            types: resolve(selected)?,
+            types_not: resolve(negated)?,
+            has_includes: globs.iter().any(|(_, negated)| !negated),
+            globs,
+        })
+    }
+
+    // Whether a path found while walking is searched, or for directories
+    // walked. The path is relative to the directory being walked.
+    pub fn is_match(&self, relative: &str, is_dir: bool) -> bool {
+        let glob = self
+            .globs
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.is_match(relative));
+        if is_dir {
+            return !glob.is_some_and(|(_, negated)| *negated);
+        }
+        match glob {
+            Some((_, true)) => return false,
+            None if self.has_includes => return false,
+            _ => {}
+        }
+
+        // Types only look at the file name, and a file must pass them too.
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        if self.types_not.iter().any(|glob| glob.is_match(name)) {
+            return false;
+        }
+        self.types.is_empty() || self.types.iter().any(|glob| glob.is_match(name))
+    }
+}
+
+// Parses a --glob pattern, anchoring it to the root when it holds a '/'.
+fn parse_glob(pattern: &str) -> (Glob, bool) {
+    let (pattern, negated) = match pattern.strip_prefix('!') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let glob = match pattern.strip_prefix('/') {
+        Some(anchored) => Glob::new(anchored),
+        None if pattern.contains('/') => Glob::new(pattern),
+        None => Glob::new(&format!("**/{pattern}")),
+    };
+    (glob, negated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn filter(selected: &[&str], negated: &[&str], globs: &[&str]) -> FileFilter {
+        let mut types = Types::default();
+        types.add("proto:*.proto").unwrap();
+        let (selected, negated, globs) = (strings(selected), strings(negated), strings(globs));
+        FileFilter::new(&types, &selected, &negated, &globs).unwrap()
+    }
+
+    #[test]
+    fn types() {
+        let rust_toml = filter(&["rust", "toml"], &[], &[]);
+        assert!(rust_toml.is_match("src/lib.rs", false));
+        assert!(rust_toml.is_match("Cargo.toml", false));
+        assert!(!rust_toml.is_match("README.md", false));
+        assert!(rust_toml.is_match("docs", true));
+
+        let not_md = filter(&[], &["md"], &[]);
+        assert!(!not_md.is_match("docs/README.md", false));
+        assert!(not_md.is_match("src/lib.rs", false));
+
+        assert!(filter(&["proto"], &[], &[]).is_match("api/a.proto", false));
+        let unknown = FileFilter::new(&Types::default(), &strings(&["nope"]), &[], &[]);
+        assert_eq!("nope", unknown.unwrap_err());
+    }
+
+    #[test]
+    fn globs() {
+        let rust = filter(&[], &[], &["*.rs", "!target", "!src/generated/*.rs"]);
+        assert!(rust.is_match("src/lib.rs", false));
+        assert!(!rust.is_match("src/generated/parser.rs", false));
+        assert!(!rust.is_match("Cargo.toml", false));
+        assert!(!rust.is_match("target", true));
+        assert!(rust.is_match("src", true));
+
+        let not_md = filter(&[], &[], &["!*.md"]);
+        assert!(!not_md.is_match("a/README.md", false));
+        assert!(not_md.is_match("a/lib.rs", false));
+    }
+
+    #[test]
+    fn type_definitions() {
+        let mut types = Types::default();
+        assert!(types.add("web:*.vue").is_ok());
+        assert!(types.add("rust:*.rs.in").is_ok());
+        assert!(types.add("novalue").is_err());
+        assert!(types.add(":*.x").is_err());
+        assert_eq!(2, types.globs("rust").unwrap().len());
+    }
+}
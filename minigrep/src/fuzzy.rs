@@ -0,0 +1,256 @@
+// Approximate matching for --fuzzy: finding the parts of a line within a
+// Levenshtein distance of the query, the number of characters inserted,
+// deleted or substituted to turn one into the other.
+//
+// The distances are computed with Myers' bit-parallel algorithm. The usual
+// dynamic programming table has a row for every character of the query and
+// a column for every character of the line, where each cell is the distance
+// of a prefix of the query from a part of the line ending there. Adjacent
+// cells differ by -1, 0 or +1, so a column is kept as two bit vectors, the
+// positions where it goes up and where it goes down, and the next column is
+// computed from them with a few bit operations whatever the query's length.
+// The query is at most 64 characters long, so a column fits a u64.
+//
+// The last row of a column is the distance of the whole query from the best
+// match ending there. Where it is lowest and within the limit a match ends,
+// and its start is found by going back from the end with the plain table.
+
+use crate::casefold;
+use crate::matcher::Matcher;
+
+// The longest query that can be searched, the bits of a column.
+pub const MAX_QUERY_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+    // The characters of the query, folded with ignore_case.
+    query: Vec<char>,
+    max_distance: usize,
+    ignore_case: bool,
+    // For every character, the positions it has in the query as bits.
+    ascii: [u64; 128],
+    other: Vec<(char, u64)>,
+}
+
+impl Fuzzy {
+    // None when the query is longer than MAX_QUERY_LEN characters.
+    pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Option<Fuzzy> {
+        let query: Vec<char> = match ignore_case {
+            true => query.chars().map(casefold::fold).collect(),
+            false => query.chars().collect(),
+        };
+        if query.len() > MAX_QUERY_LEN {
+            return None;
+        }
+
+        let mut ascii = [0; 128];
+        let mut other: Vec<(char, u64)> = Vec::new();
+        for (i, c) in query.iter().enumerate() {
+            match ascii.get_mut(*c as usize) {
+                Some(bits) => *bits |= 1 << i,
+                None => match other.iter_mut().find(|(other, _)| other == c) {
+                    Some((_, bits)) => *bits |= 1 << i,
+                    None => other.push((*c, 1 << i)),
+                },
+            }
+        }
+        Some(Fuzzy {
+            query,
+            max_distance,
+            ignore_case,
+            ascii,
+            other,
+        })
+    }
+
+    fn fold(&self, c: char) -> char {
+        match self.ignore_case {
+            true => casefold::fold(c),
+            false => c,
+        }
+    }
+
+    // The positions of c in the query.
+    fn positions(&self, c: char) -> u64 {
+        match self.ascii.get(c as usize) {
+            Some(bits) => *bits,
+            None => self
+                .other
+                .iter()
+                .find(|(other, _)| *other == c)
+                .map_or(0, |(_, bits)| *bits),
+        }
+    }
+
+    // The matches within the line with their distance from the query, from
+    // left to right without overlaps. Of the matches around one place, the
+    // closest one is kept, and the shortest of those.
+    pub fn find_distances(&self, line: &str) -> Vec<(usize, usize, usize)> {
+        let chars: Vec<(usize, char)> = line
+            .char_indices()
+            .map(|(at, c)| (at, self.fold(c)))
+            .collect();
+        // The byte offset where the character at index starts, or the end.
+        let offset = |index: usize| chars.get(index).map_or(line.len(), |(at, _)| *at);
+
+        let len = self.query.len();
+        if len == 0 {
+            return vec![(0, 0, 0)];
+        }
+        let last = 1 << (len - 1);
+        // The column before the line: the distance from nothing grows by
+        // one with every character of the query.
+        let mut up = u64::MAX >> (MAX_QUERY_LEN - len);
+        let mut down = 0u64;
+        let mut distance = len;
+
+        // The distance at the end of every character.
+        let mut distances = Vec::with_capacity(chars.len() + 1);
+        distances.push(len);
+        for (_, c) in &chars {
+            let eq = self.positions(*c);
+            let xv = eq | down;
+            let xh = (((eq & up).wrapping_add(up)) ^ up) | eq;
+            let mut horizontal_up = down | !(xh | up);
+            let mut horizontal_down = up & xh;
+            if horizontal_up & last != 0 {
+                distance += 1;
+            } else if horizontal_down & last != 0 {
+                distance -= 1;
+            }
+            // A match may start anywhere, so the first row stays at zero.
+            horizontal_up <<= 1;
+            horizontal_down <<= 1;
+            up = horizontal_down | !(xv | horizontal_up);
+            down = horizontal_up & xv;
+            distances.push(distance);
+        }
+
+        let mut found = Vec::new();
+        // Where the last match ended, so the next one can't overlap it.
+        let mut last_end = 0;
+        for end in 1..distances.len() {
+            let distance = distances[end];
+            // The first end of the lowest distance around, where it stops
+            // going down and before it goes up again.
+            let lowest = distance < distances[end - 1]
+                && distances.get(end + 1).is_none_or(|next| distance <= *next);
+            if distance > self.max_distance || !lowest {
+                continue;
+            }
+            let start = self.start(&chars[..end], distance);
+            if start >= last_end {
+                found.push((offset(start), offset(end), distance));
+                last_end = end;
+            }
+        }
+        found
+    }
+
+    // The start of the shortest match ending at the end of text that is
+    // distance away from the query, going back from the end. A match that
+    // far away has at most distance characters more than the query, so no
+    // more than those are looked at, however long the line.
+    fn start(&self, text: &[(usize, char)], distance: usize) -> usize {
+        let len = self.query.len();
+        let longest = len + distance;
+        // column[i] is the distance of the last i characters of the query
+        // from the characters of text taken so far.
+        let mut column: Vec<usize> = (0..=len).collect();
+        if column[len] == distance {
+            return text.len();
+        }
+        for (taken, (_, c)) in text.iter().rev().take(longest).enumerate() {
+            let mut diagonal = column[0];
+            column[0] = taken + 1;
+            for i in 1..=len {
+                let substituted = diagonal + (self.query[len - i] != *c) as usize;
+                diagonal = column[i];
+                column[i] = substituted.min(column[i] + 1).min(column[i - 1] + 1);
+            }
+            if column[len] <= distance {
+                return text.len() - taken - 1;
+            }
+        }
+        text.len().saturating_sub(longest)
+    }
+
+    // The Levenshtein distance of the query from the whole text.
+    pub fn distance(&self, text: &str) -> usize {
+        let len = self.query.len();
+        let mut row: Vec<usize> = (0..=len).collect();
+        for (taken, c) in text.chars().map(|c| self.fold(c)).enumerate() {
+            let mut diagonal = row[0];
+            row[0] = taken + 1;
+            for i in 1..=len {
+                let substituted = diagonal + (self.query[i - 1] != c) as usize;
+                diagonal = row[i];
+                row[i] = substituted.min(row[i] + 1).min(row[i - 1] + 1);
+            }
+        }
+        row[len]
+    }
+}
+
+impl Matcher for Fuzzy {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        self.find_distances(line)
+            .into_iter()
+            .map(|(start, end, _)| (start, end))
+            .collect()
+    }
+
+    fn distance(&self, found: &str) -> Option<usize> {
+        Some(Fuzzy::distance(self, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(query: &str, max_distance: usize, line: &str) -> Vec<(usize, usize, usize)> {
+        Fuzzy::new(query, max_distance, false)
+            .unwrap()
+            .find_distances(line)
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(vec![(4, 10, 0)], find("config", 1, "let config = 1;"));
+        // A substitution, a deletion and an insertion.
+        assert_eq!(vec![(4, 10, 1)], find("config", 1, "let confog = 1;"));
+        assert_eq!(vec![(4, 9, 1)], find("config", 1, "let confg = 1;"));
+        assert_eq!(vec![(4, 11, 1)], find("config", 2, "let connfig = 1;"));
+        assert_eq!(
+            Vec::<(usize, usize, usize)>::new(),
+            find("config", 1, "let cnofgi")
+        );
+        assert_eq!(vec![(0, 3, 0), (3, 6, 0)], find("abc", 1, "abcabc"));
+        assert_eq!(vec![(0, 2, 1)], find("abc", 1, "abxc"));
+
+        // Offsets are in bytes, whatever the characters.
+        assert_eq!(vec![(4, 10, 1)], find("straße", 1, "ß: strase"));
+        let folded = Fuzzy::new("RÉSUMÉ", 1, true).unwrap();
+        assert_eq!(vec![(0, 6, 1)], folded.find_distances("résume"));
+        assert_eq!(1, folded.distance("résume"));
+
+        // Every bit of a u64 is used by the longest query.
+        let long = "x".repeat(MAX_QUERY_LEN);
+        assert_eq!(
+            vec![(1, 64, 1)],
+            find(&long, 1, &format!("y{}", &long[1..]))
+        );
+        assert!(Fuzzy::new(&"x".repeat(MAX_QUERY_LEN + 1), 1, false).is_none());
+    }
+
+    #[test]
+    fn long_lines() {
+        // A match ends every few characters, and each start is found
+        // without going back over the whole line before it.
+        let line = "abxd".repeat(250_000);
+        let found = find("abcd", 1, &line);
+        assert_eq!(250_000, found.len());
+        assert_eq!(Some(&(999_996, 1_000_000, 1)), found.last());
+    }
+}
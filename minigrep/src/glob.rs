@@ -0,0 +1,211 @@
+// Shell style glob patterns matched against '/' separated relative paths,
+// following the rules used by .gitignore files:
+//  * matches anything except '/'
+//  ? matches any single character except '/'
+//  [a-z] matches a character class, [!a-z] or [^a-z] negates it
+//  **/ at the start or /**/ in the middle matches any number of directories
+//  /** at the end matches everything inside a directory
+// A backslash escapes the next character.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Star,
+    // Zero or more whole directories, "" or "a/" or "a/b/".
+    AnyDirs,
+    // Anything at all, including '/'.
+    AnyPath,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    // Every pattern is valid, a '[' without a closing ']' is taken literally.
+    pub fn new(pattern: &str) -> Glob {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Literal(chars[i + 1]));
+                    i += 2;
+                }
+                '*' => {
+                    let start = i;
+                    while i < chars.len() && chars[i] == '*' {
+                        i += 1;
+                    }
+                    let whole_segment = (start == 0 || chars[start - 1] == '/')
+                        && (i == chars.len() || chars[i] == '/');
+
+                    if i - start < 2 || !whole_segment {
+                        tokens.push(Token::Star);
+                    } else if i == chars.len() {
+                        tokens.push(Token::AnyPath);
+                    } else {
+                        // The slash after ** is part of the directories it matches.
+                        tokens.push(Token::AnyDirs);
+                        i += 1;
+                    }
+                }
+                '?' => {
+                    tokens.push(Token::Any);
+                    i += 1;
+                }
+                '[' => match parse_class(&chars, i) {
+                    Some((class, end)) => {
+                        tokens.push(class);
+                        i = end;
+                    }
+                    None => {
+                        tokens.push(Token::Literal('['));
+                        i += 1;
+                    }
+                },
+                c => {
+                    tokens.push(Token::Literal(c));
+                    i += 1;
+                }
+            }
+        }
+
+        Glob { tokens }
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let chars: Vec<char> = path.chars().collect();
+        // Remembers states that are known not to match, which keeps stars
+        // from backtracking over the same input again and again.
+        let mut failed = vec![false; (self.tokens.len() + 1) * (chars.len() + 1)];
+        self.match_from(0, &chars, 0, &mut failed)
+    }
+
+    fn match_from(&self, token: usize, chars: &[char], pos: usize, failed: &mut [bool]) -> bool {
+        let state = token * (chars.len() + 1) + pos;
+        if failed[state] {
+            return false;
+        }
+
+        let next = token + 1;
+        let matched = match self.tokens.get(token) {
+            None => pos == chars.len(),
+            Some(Token::Literal(c)) => {
+                chars.get(pos) == Some(c) && self.match_from(next, chars, pos + 1, failed)
+            }
+            Some(Token::Any) => {
+                chars.get(pos).is_some_and(|c| *c != '/')
+                    && self.match_from(next, chars, pos + 1, failed)
+            }
+            Some(Token::Class(ranges, negated)) => {
+                chars.get(pos).is_some_and(|c| {
+                    *c != '/' && ranges.iter().any(|(low, high)| low <= c && c <= high) != *negated
+                }) && self.match_from(next, chars, pos + 1, failed)
+            }
+            Some(Token::Star) => {
+                let mut end = pos;
+                loop {
+                    if self.match_from(next, chars, end, failed) {
+                        break true;
+                    }
+                    if end == chars.len() || chars[end] == '/' {
+                        break false;
+                    }
+                    end += 1;
+                }
+            }
+            Some(Token::AnyDirs) => {
+                self.match_from(next, chars, pos, failed)
+                    || (pos..chars.len())
+                        .filter(|end| chars[*end] == '/')
+                        .any(|end| self.match_from(next, chars, end + 1, failed))
+            }
+            Some(Token::AnyPath) => {
+                (pos..=chars.len()).any(|end| self.match_from(next, chars, end, failed))
+            }
+        };
+
+        if !matched {
+            failed[state] = true;
+        }
+        matched
+    }
+}
+
+// Parses the class starting at chars[open] == '['. Returns the token and the
+// index after the closing ']', or None when the class is never closed.
+fn parse_class(chars: &[char], open: usize) -> Option<(Token, usize)> {
+    let mut i = open + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let first = i;
+    loop {
+        let mut low = *chars.get(i)?;
+        // A ']' right after the opening bracket is a literal.
+        if low == ']' && i > first {
+            return Some((Token::Class(ranges, negated), i + 1));
+        }
+        if low == '\\' {
+            i += 1;
+            low = *chars.get(i)?;
+        }
+        i += 1;
+
+        let mut high = low;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|c| *c != ']') {
+            high = chars[i + 1];
+            i += 2;
+        }
+        ranges.push((low, high));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(Glob::new("*.rs").is_match("lib.rs"));
+        assert!(!Glob::new("*.rs").is_match("src/lib.rs"));
+        assert!(Glob::new("src/*.rs").is_match("src/lib.rs"));
+        assert!(Glob::new("l?b.rs").is_match("lib.rs"));
+        assert!(!Glob::new("a?b").is_match("a/b"));
+        assert!(Glob::new("[a-c]x[!0-9]").is_match("bxy"));
+        assert!(!Glob::new("[a-c]x[!0-9]").is_match("bx1"));
+        assert!(Glob::new("[]]").is_match("]"));
+        assert!(Glob::new("a[b").is_match("a[b"));
+        assert!(Glob::new(r"\*").is_match("*"));
+        assert!(!Glob::new(r"\*").is_match("a"));
+    }
+
+    #[test]
+    fn double_star() {
+        let glob = Glob::new("**/target");
+        assert!(glob.is_match("target"));
+        assert!(glob.is_match("a/b/target"));
+        assert!(!glob.is_match("a/target/b"));
+
+        let glob = Glob::new("a/**/b");
+        assert!(glob.is_match("a/b"));
+        assert!(glob.is_match("a/x/y/b"));
+        assert!(!glob.is_match("ab"));
+
+        let glob = Glob::new("vendor/**");
+        assert!(glob.is_match("vendor/x/y.rs"));
+        assert!(!glob.is_match("vendor"));
+
+        // Not a whole path segment, so it is an ordinary star.
+        assert!(!Glob::new("a**b").is_match("a/b"));
+    }
+}
@@ -0,0 +1,246 @@
+// Support for .gitignore and .ignore files. Every directory can hold ignore
+// files whose rules apply to paths below it, with rules in deeper
+// directories taking precedence over those further up. Like git, a search
+// within a repository also reads the ignore files of the directories above
+// it up to the top of the repository, and .git/info/exclude.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::glob::Glob;
+
+// The files read from every directory, later files take precedence.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    // A rule starting with '!' re-includes paths excluded by earlier rules.
+    negated: bool,
+    // A rule ending in '/' only matches directories.
+    dir_only: bool,
+}
+
+// The rules of one ignore file. Patterns are relative to root.
+#[derive(Debug)]
+pub struct Gitignore {
+    root: PathBuf,
+    // For a file above the search, the path from its directory down to
+    // root, which the paths found below root are joined to.
+    prefix: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    pub fn parse(root: impl AsRef<Path>, contents: &str) -> Gitignore {
+        Gitignore {
+            root: root.as_ref().to_path_buf(),
+            prefix: PathBuf::new(),
+            rules: contents.lines().filter_map(parse_rule).collect(),
+        }
+    }
+
+    // Some(true) if the path is ignored, Some(false) if a negated rule
+    // re-includes it and None if no rule mentions it.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = self.prefix.join(path.strip_prefix(&self.root).ok()?);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+
+        // The last matching rule decides, so search from the end.
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(&relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Trailing spaces are dropped unless escaped with a backslash.
+    let mut line = line.trim_end_matches('\r');
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let negated = line.starts_with('!');
+    if negated {
+        line = &line[1..];
+    }
+    let dir_only = line.ends_with('/');
+    if dir_only {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() {
+        return None;
+    }
+
+    // A slash at the start or in the middle anchors the pattern to the
+    // directory of the ignore file, otherwise it matches at any depth.
+    let glob = match line.strip_prefix('/') {
+        Some(anchored) => Glob::new(anchored),
+        None if line.contains('/') => Glob::new(line),
+        None => Glob::new(&format!("**/{line}")),
+    };
+
+    Some(Rule {
+        glob,
+        negated,
+        dir_only,
+    })
+}
+
+// The ignore files that apply within one directory, from the least to the
+// most specific. Directories share their parent's files through Arc.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreStack {
+    files: Vec<Arc<Gitignore>>,
+}
+
+impl IgnoreStack {
+    // The stack for a search rooted at root, holding the user's global
+    // git ignore file if there is one, and the files of the repository
+    // above root.
+    pub fn new(root: &Path) -> IgnoreStack {
+        let mut stack = IgnoreStack::default();
+        if let Some(contents) = global_path().and_then(|path| fs::read_to_string(path).ok()) {
+            stack
+                .files
+                .push(Arc::new(Gitignore::parse(root, &contents)));
+        }
+        stack.push_repository(root);
+        stack
+    }
+
+    // Adds .git/info/exclude and the ignore files from the top of the
+    // repository holding root down to the directory above it. The files in
+    // root itself are added by child like those below it. Outside of a
+    // repository there is nothing to add.
+    fn push_repository(&mut self, root: &Path) {
+        let Ok(absolute) = fs::canonicalize(root) else {
+            return;
+        };
+        let Some(top) = absolute.ancestors().find(|dir| dir.join(".git").is_dir()) else {
+            return;
+        };
+        // The file's rules match paths below root as if they were found
+        // from the directory holding the file.
+        let mut push = |dir: &Path, contents: &str| {
+            let mut file = Gitignore::parse(root, contents);
+            file.prefix = absolute
+                .strip_prefix(dir)
+                .unwrap_or(&absolute)
+                .to_path_buf();
+            self.files.push(Arc::new(file));
+        };
+
+        if let Ok(contents) = fs::read_to_string(top.join(".git/info/exclude")) {
+            push(top, &contents);
+        }
+        let mut dir = top.to_path_buf();
+        for component in absolute.strip_prefix(top).unwrap_or(Path::new("")) {
+            for name in IGNORE_FILES {
+                if let Ok(contents) = fs::read_to_string(dir.join(name)) {
+                    push(&dir, &contents);
+                }
+            }
+            dir.push(component);
+        }
+    }
+
+    // The stack for the entries of dir, adding the ignore files found in it.
+    pub fn child(&self, dir: &Path) -> IgnoreStack {
+        let mut stack = self.clone();
+        for name in IGNORE_FILES {
+            // Missing or unreadable ignore files are simply not used.
+            if let Ok(contents) = fs::read_to_string(dir.join(name)) {
+                stack.files.push(Arc::new(Gitignore::parse(dir, &contents)));
+            }
+        }
+        stack
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.files
+            .iter()
+            .rev()
+            .find_map(|file| file.matched(path, is_dir))
+            .unwrap_or(false)
+    }
+}
+
+// The global ignore file git reads by default, $XDG_CONFIG_HOME/git/ignore
+// falling back to ~/.config/git/ignore.
+fn global_path() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => Some(PathBuf::from(config).join("git/ignore")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(contents: &str, path: &str, is_dir: bool) -> Option<bool> {
+        Gitignore::parse("root", contents).matched(&Path::new("root").join(path), is_dir)
+    }
+
+    #[test]
+    fn unanchored_and_anchored() {
+        assert_eq!(Some(true), ignored("*.o", "a/b/x.o", false));
+        assert_eq!(Some(true), ignored("target", "a/target", true));
+        assert_eq!(Some(true), ignored("/target", "target", true));
+        assert_eq!(None, ignored("/target", "a/target", true));
+        assert_eq!(Some(true), ignored("doc/*.md", "doc/a.md", false));
+        assert_eq!(None, ignored("doc/*.md", "x/doc/a.md", false));
+        assert_eq!(
+            Some(true),
+            ignored("**/build/*.log", "x/build/a.log", false)
+        );
+    }
+
+    #[test]
+    fn negation_and_directories() {
+        let contents = "\
+# comments and blank lines are skipped
+
+*.log
+!keep.log
+build/
+";
+        assert_eq!(Some(true), ignored(contents, "a.log", false));
+        assert_eq!(Some(false), ignored(contents, "x/keep.log", false));
+        assert_eq!(Some(true), ignored(contents, "build", true));
+        assert_eq!(None, ignored(contents, "build", false));
+        assert_eq!(None, ignored(contents, "notes.txt", false));
+    }
+
+    #[test]
+    fn escapes_and_trailing_spaces() {
+        assert_eq!(Some(true), ignored(r"\#notes", "#notes", false));
+        assert_eq!(Some(true), ignored(r"\!bang", "!bang", false));
+        assert_eq!(Some(true), ignored("trailing   ", "trailing", false));
+        assert_eq!(Some(true), ignored(r"space\ ", "space ", false));
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let mut stack = IgnoreStack::default();
+        stack
+            .files
+            .push(Arc::new(Gitignore::parse("root", "*.txt")));
+        stack
+            .files
+            .push(Arc::new(Gitignore::parse("root/sub", "!keep.txt")));
+
+        assert!(stack.is_ignored(Path::new("root/a.txt"), false));
+        assert!(stack.is_ignored(Path::new("root/sub/a.txt"), false));
+        assert!(!stack.is_ignored(Path::new("root/sub/keep.txt"), false));
+        assert!(!stack.is_ignored(Path::new("root/a.rs"), false));
+    }
+}
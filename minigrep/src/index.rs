@@ -0,0 +1,507 @@
+// A trigram index of a directory tree, built by `minigrep index DIR` and
+// used by searches with --index. For every file it holds the set of
+// trigrams, runs of three bytes, in its contents. A file can only contain a
+// fixed string if it contains every trigram of the string, so a search only
+// needs to read the files holding all of them.
+//
+// The trigrams are taken after lowercasing ASCII letters, so one index
+// serves both case-sensitive and case-insensitive searches. The index also
+// records the modification time and size of every file. Building it again
+// only reads the files that changed, and a search falls back to scanning
+// every file when the tree no longer matches the index.
+//
+// The index is stored in DIR/.minigrep-index, in this format, with numbers
+// in little endian:
+//  "MGIX", u32 version, u32 number of files, then for every file:
+//  u32 path length, the path relative to DIR in UTF-8, u64 seconds and u32
+//  nanoseconds of the modification time, u64 size, u32 number of trigrams,
+//  and the sorted trigrams, three bytes each.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::error::Error;
+use crate::walk::{Walk, WalkOptions};
+use crate::{Config, OutputMode};
+
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+// The ASCII letters that a non-ASCII character folds to as well: 'k' for
+// the Kelvin sign and 's' for the long s.
+const FOLDED_FROM_NON_ASCII: [u8; 2] = [b'k', b's'];
+
+const MAGIC: &[u8; 4] = b"MGIX";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    // Relative to the root of the index.
+    path: PathBuf,
+    modified: (u64, u32),
+    size: u64,
+    trigrams: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    root: PathBuf,
+    // In the order the files are walked.
+    entries: Vec<Entry>,
+}
+
+// What building an index did, for the message printed afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildStats {
+    pub files: usize,
+    // Files that hadn't changed since the last build, so weren't read.
+    pub unchanged: usize,
+    // Files that couldn't be read, which were reported and left out.
+    pub errors: usize,
+}
+
+impl Index {
+    // Indexes the files below root, reusing the entries of the previous
+    // index for files with the same modification time and size. A file that
+    // can't be read is reported and left out, the rest are still indexed.
+    pub fn build(
+        root: &Path,
+        options: &WalkOptions,
+        previous: Option<&Index>,
+    ) -> (Index, BuildStats) {
+        let previous: HashMap<&Path, &Entry> = previous
+            .map(|index| {
+                let entries = index.entries.iter();
+                entries.map(|entry| (entry.path.as_path(), entry)).collect()
+            })
+            .unwrap_or_default();
+
+        let mut entries = Vec::new();
+        let mut unchanged = 0;
+        let mut errors = 0;
+        for (path, relative) in files(root, options) {
+            let stat = path.and_then(|path| Ok((stat(&path)?, path)));
+            let ((modified, size), path) = match stat {
+                Ok(stat) => stat,
+                Err(err) => {
+                    crate::print_error(&err);
+                    errors += 1;
+                    continue;
+                }
+            };
+            match previous.get(relative.as_path()) {
+                Some(entry) if entry.modified == modified && entry.size == size => {
+                    entries.push((*entry).clone());
+                    unchanged += 1;
+                }
+                _ => {
+                    let contents = match fs::read(&path) {
+                        Ok(contents) => contents,
+                        Err(err) => {
+                            crate::print_error(&Error::io(&path, err));
+                            errors += 1;
+                            continue;
+                        }
+                    };
+                    let trigrams = trigrams(&contents);
+                    entries.push(Entry {
+                        path: relative,
+                        modified,
+                        size,
+                        trigrams,
+                    });
+                }
+            }
+        }
+
+        let stats = BuildStats {
+            files: entries.len(),
+            unchanged,
+            errors,
+        };
+        let index = Index {
+            root: root.to_path_buf(),
+            entries,
+        };
+        (index, stats)
+    }
+
+    // The index stored in root, None if there is none.
+    pub fn load(root: &Path) -> Result<Option<Index>, Error> {
+        let path = root.join(INDEX_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::io(&path, err)),
+        };
+        match decode(root, &bytes) {
+            Some(index) => Ok(Some(index)),
+            None => {
+                let message = "not a valid index, build it again";
+                let source = io::Error::new(io::ErrorKind::InvalidData, message);
+                Err(Error::Io { path, source })
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let path = self.root.join(INDEX_FILE);
+        crate::replace::replace_file(&path, &self.encode()).map_err(|err| Error::io(&path, err))
+    }
+
+    // The files below the root that may hold a match, in the order they are
+    // walked, or None when any of them changed since it was indexed. Every
+    // element of required lists the trigrams of one pattern, and a file may
+    // match when it has all the trigrams of any of them. Only the metadata
+    // of the files is read. A file that can't be walked to or read is an
+    // error in its place, for the search to report like any other.
+    pub fn candidates(
+        &self,
+        options: &WalkOptions,
+        required: &[Vec<u32>],
+    ) -> Option<Vec<Result<PathBuf, Error>>> {
+        let entries: HashMap<&Path, &Entry> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_path(), entry))
+            .collect();
+
+        let mut candidates = Vec::new();
+        for (path, relative) in files(&self.root, options) {
+            let stat = path.and_then(|path| Ok((stat(&path)?, path)));
+            let (stat, path) = match stat {
+                Ok(stat) => stat,
+                Err(err) => {
+                    candidates.push(Err(err));
+                    continue;
+                }
+            };
+            let entry = match entries.get(relative.as_path()) {
+                Some(entry) if (entry.modified, entry.size) == stat => entry,
+                _ => return None,
+            };
+            let has = |trigram: &u32| entry.trigrams.binary_search(trigram).is_ok();
+            if required.iter().any(|trigrams| trigrams.iter().all(has)) {
+                candidates.push(Ok(path));
+            }
+        }
+        Some(candidates)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            let path = entry.path.to_string_lossy().replace('\\', "/");
+            bytes.extend((path.len() as u32).to_le_bytes());
+            bytes.extend(path.as_bytes());
+            bytes.extend(entry.modified.0.to_le_bytes());
+            bytes.extend(entry.modified.1.to_le_bytes());
+            bytes.extend(entry.size.to_le_bytes());
+            bytes.extend((entry.trigrams.len() as u32).to_le_bytes());
+            for trigram in &entry.trigrams {
+                bytes.extend(&trigram.to_le_bytes()[..3]);
+            }
+        }
+        bytes
+    }
+}
+
+// Reads an index, None if the bytes don't hold one.
+fn decode(root: &Path, bytes: &[u8]) -> Option<Index> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+        return None;
+    }
+
+    let count = reader.u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let len = reader.u32()? as usize;
+        let path = PathBuf::from(std::str::from_utf8(reader.take(len)?).ok()?);
+        let modified = (reader.u64()?, reader.u32()?);
+        let size = reader.u64()?;
+        let trigrams = reader.u32()? as usize;
+        let trigrams = reader
+            .take(trigrams.checked_mul(3)?)?
+            .chunks(3)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+            .collect();
+        entries.push(Entry {
+            path,
+            modified,
+            size,
+            trigrams,
+        });
+    }
+
+    reader.bytes.is_empty().then(|| Index {
+        root: root.to_path_buf(),
+        entries,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+// The files below root that are indexed, with their paths relative to root.
+fn files<'a>(
+    root: &'a Path,
+    options: &WalkOptions,
+) -> impl Iterator<Item = (Result<PathBuf, Error>, PathBuf)> + 'a {
+    Walk::new(root, options)
+        .map(move |path| {
+            let relative = match &path {
+                Ok(path) => path.strip_prefix(root).unwrap_or(path).to_path_buf(),
+                Err(_) => PathBuf::new(),
+            };
+            (path, relative)
+        })
+        .filter(|(_, relative)| relative.file_name() != Some(INDEX_FILE.as_ref()))
+}
+
+fn stat(path: &Path) -> Result<((u64, u32), u64), Error> {
+    let metadata = fs::metadata(path).map_err(|err| Error::io(path, err))?;
+    let modified = metadata
+        .modified()
+        .map_err(|err| Error::io(path, err))?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((
+        (modified.as_secs(), modified.subsec_nanos()),
+        metadata.len(),
+    ))
+}
+
+// The sorted, distinct trigrams of the text, with ASCII letters lowercased.
+pub fn trigrams(text: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = text
+        .windows(3)
+        .map(|window| {
+            let [a, b, c] = [0, 1, 2].map(|i| window[i].to_ascii_lowercase() as u32);
+            a | (b << 8) | (c << 16)
+        })
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+// The trigrams a file must have for each pattern of the search to match in
+// it, None when the index can't narrow down the files for this search.
+pub fn required_trigrams(config: &Config) -> Option<Vec<Vec<u32>>> {
+    // Regular expressions have no fixed strings to look for, fuzzy matches
+    // needn't hold the query, and inverted searches or -L are about the
+    // lines or files without them. Compressed files are indexed as they are
+    // on disk.
+    if config.regex.is_some()
+        || config.fuzzy.is_some()
+        || config.invert
+        || config.mode == OutputMode::FilesWithoutMatch
+        || config.search_zip
+    {
+        return None;
+    }
+
+    let mut required = Vec::new();
+    for pattern in &config.patterns {
+        let mut pattern_trigrams = trigrams(pattern.as_bytes());
+        // Beyond ASCII, the case folding of a search doesn't map to the bytes
+        // the index lowercased, so those trigrams can't be relied on. Nor
+        // can those with a letter a file may spell with a non-ASCII character.
+        if config.ignore_case {
+            pattern_trigrams.retain(|trigram| {
+                let bytes = &trigram.to_le_bytes()[..3];
+                bytes
+                    .iter()
+                    .all(|byte| byte.is_ascii() && !FOLDED_FROM_NON_ASCII.contains(byte))
+            });
+        }
+        // A pattern without any trigrams may match in any file.
+        if pattern_trigrams.is_empty() {
+            return None;
+        }
+        required.push(pattern_trigrams);
+    }
+    Some(required)
+}
+
+// Builds or updates the index of every path, for `minigrep index`.
+pub fn run(config: &Config) -> Result<(), Error> {
+    let default = [String::from(".")];
+    let paths = match config.paths.is_empty() {
+        true => &default[..],
+        false => &config.paths[..],
+    };
+
+    let mut errors = 0;
+    for path in paths {
+        let root = Path::new(path);
+        if !root.is_dir() {
+            let message = "only directories can be indexed";
+            let source = io::Error::new(io::ErrorKind::InvalidInput, message);
+            return Err(Error::io(root, source));
+        }
+        // An unreadable index is simply built again from scratch.
+        let previous = Index::load(root).unwrap_or(None);
+        let (index, stats) = Index::build(root, &config.walk, previous.as_ref());
+        index.save()?;
+        println!(
+            "{path}: indexed {} files, {} unchanged",
+            stats.files, stats.unchanged
+        );
+        errors += stats.errors;
+    }
+    match errors {
+        0 => Ok(()),
+        errors => Err(Error::Inputs(errors)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("minigrep-index-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn search() {}\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("README.md"), "Search Tool\n").unwrap();
+        root
+    }
+
+    // The relative paths of the candidates, None if the index is stale.
+    fn names(index: &Index, patterns: &[&str]) -> Option<Vec<String>> {
+        let required: Vec<Vec<u32>> = patterns.iter().map(|q| trigrams(q.as_bytes())).collect();
+        let candidates = index.candidates(&WalkOptions::default(), &required);
+        let names = candidates?.into_iter().map(|path| {
+            let path = path.unwrap();
+            let relative = path.strip_prefix(&index.root).unwrap();
+            relative.to_string_lossy().replace('\\', "/")
+        });
+        Some(names.collect())
+    }
+
+    #[test]
+    fn trigram_sets() {
+        assert_eq!(
+            vec![
+                u32::from_le_bytes([b'a', b'b', b'a', 0]),
+                u32::from_le_bytes([b'b', b'a', b'b', 0])
+            ],
+            trigrams(b"ABaBab")
+        );
+        assert!(trigrams(b"ab").is_empty());
+    }
+
+    #[test]
+    fn narrows_candidates() {
+        let root = tree("narrow");
+        let (index, stats) = Index::build(&root, &WalkOptions::default(), None);
+        assert_eq!(3, stats.files);
+
+        let names = |patterns| names(&index, patterns).unwrap();
+        assert_eq!(vec!["README.md", "src/lib.rs"], names(&["search"]));
+        assert_eq!(vec!["src/main.rs"], names(&["main"]));
+        assert_eq!(
+            vec!["src/lib.rs", "src/main.rs"],
+            names(&["main", "pub fn"])
+        );
+        assert!(names(&["missing"]).is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn saves_and_updates() {
+        let root = tree("update");
+        let options = WalkOptions::default();
+        let (index, _) = Index::build(&root, &options, None);
+        index.save().unwrap();
+        let loaded = Index::load(&root).unwrap().unwrap();
+        assert_eq!(index, loaded);
+
+        // A changed size is enough to notice a change, whatever the clock.
+        fs::write(root.join("src/main.rs"), "fn main() { search(); }\n").unwrap();
+        assert_eq!(None, names(&loaded, &["search"]));
+        let (index, stats) = Index::build(&root, &options, Some(&loaded));
+        assert_eq!(2, stats.unchanged);
+        assert_eq!(
+            vec!["README.md", "src/lib.rs", "src/main.rs"],
+            names(&index, &["search"]).unwrap()
+        );
+
+        fs::write(root.join("new.txt"), "").unwrap();
+        assert_eq!(None, names(&index, &["search"]));
+
+        fs::write(root.join(INDEX_FILE), "garbage").unwrap();
+        assert!(Index::load(&root).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore_case_trigrams() {
+        let required = |args: &[&str]| {
+            let args = ["minigrep", "--index"].iter().chain(args);
+            required_trigrams(&Config::build(args.map(|arg| arg.to_string())).unwrap())
+        };
+        assert_eq!(Some(vec![trigrams(b"key")]), required(&["key", "."]));
+        // "\u{212a}EY" matches -i key, but has none of its trigrams.
+        assert_eq!(None, required(&["-i", "key", "."]));
+        assert_eq!(Some(vec![trigrams(b"tra")]), required(&["-i", "TRAß", "."]));
+
+        let mut folded_to_ascii: Vec<u8> = (0x80..=char::MAX as u32)
+            .filter_map(char::from_u32)
+            .map(crate::casefold::fold)
+            .filter(char::is_ascii)
+            .map(|c| c as u8)
+            .collect();
+        folded_to_ascii.sort_unstable();
+        assert_eq!(FOLDED_FROM_NON_ASCII.to_vec(), folded_to_ascii);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bad_entries_are_skipped() {
+        let root = tree("dangling");
+        std::os::unix::fs::symlink("missing.rs", root.join("src/gone.rs")).unwrap();
+        let options = WalkOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+
+        // The link can't be indexed, the files next to it still are.
+        let (index, stats) = Index::build(&root, &options, None);
+        assert_eq!((3, 1), (stats.files, stats.errors));
+        let required = [trigrams(b"fn ")];
+        let candidates = index.candidates(&options, &required).unwrap();
+        let found: Vec<bool> = candidates.iter().map(Result::is_ok).collect();
+        assert_eq!(vec![false, true, true], found);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
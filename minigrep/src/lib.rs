@@ -1,88 +1,984 @@
 use std::env;
-use std::error::Error;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+pub mod aho_corasick;
+pub mod args;
+pub mod casefold;
+pub mod color;
+pub mod decompress;
+mod error;
+pub mod filter;
+pub mod fuzzy;
+pub mod glob;
+pub mod ignore;
+pub mod index;
+pub mod literal;
+pub mod matcher;
+pub mod mmap;
+mod parallel;
+pub mod printer;
+pub mod regex;
+pub mod replace;
+pub mod sink;
+pub mod walk;
+
+use aho_corasick::AhoCorasick;
+use args::{Arg, ArgsError, Parser};
+use color::{ColorChoice, ColorScheme};
+use decompress::Format;
+pub use error::Error;
+use filter::{FileFilter, Types};
+use fuzzy::Fuzzy;
+use matcher::{CaseInsensitive, Matcher, MultiPattern, Substring};
+use mmap::Mmap;
+use printer::{PrintOptions, Printer};
+use regex::Regex;
+use replace::Replacement;
+use sink::{Searcher, Sink};
+use walk::{Walk, WalkOptions};
+
+// The name printed for results read from standard input.
+const STDIN_NAME: &str = "(standard input)";
+
+// Files are read in blocks of this size, however large they are.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+// Files at least this large are memory mapped and searched as a whole.
+// Mapping a file costs more than reading a small one.
+const MMAP_THRESHOLD: u64 = 4 * 1024 * 1024;
 
 // Make struct and members public so they can be used elsewhere
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    pub command: Command,
+    // Lines matching any of the patterns are selected.
+    pub patterns: Vec<String>,
+    // Files or directories to search, directories are walked recursively.
+    // Standard input is searched for "-" or when no paths are given.
+    pub paths: Vec<String>,
     pub ignore_case: bool,
+    // The compiled patterns when searching with --regex, None for plain substring search.
+    pub regex: Option<Regex>,
+    // Match within this edit distance of the query instead of exactly.
+    pub fuzzy: Option<usize>,
+    pub walk: WalkOptions,
+    pub print: PrintOptions,
+    // Selects the lines that do not match instead.
+    pub invert: bool,
+    // Stop reading an input after this many matching lines.
+    pub max_count: Option<usize>,
+    pub mode: OutputMode,
+    // Only match whole words, bounded by non-word characters or the ends of the line.
+    pub whole_word: bool,
+    // Only match whole lines.
+    pub whole_line: bool,
+    // Search binary files as if they were text.
+    pub text: bool,
+    // Let matches span lines, reporting every line they cover.
+    pub multiline: bool,
+    // Search the contents of compressed files.
+    pub search_zip: bool,
+    // Matches are replaced by this text in the output.
+    pub replace: Option<Replacement>,
+    // Write the replaced lines back to the files instead of printing them.
+    pub in_place: bool,
+    // Print the changes made by the replacement as a unified diff.
+    pub diff: bool,
+    // The number of threads searching files, 0 for one per CPU core.
+    pub threads: usize,
+    // Print the results in the order of the paths instead of whichever
+    // thread finishes first.
+    pub sort_by_path: bool,
+    // Narrow the files searched in directories down with their index.
+    pub use_index: bool,
+}
+
+// What to do with the arguments: search, or index the directories for
+// later searches with `minigrep index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Search,
+    Index,
+}
+
+// What is printed for the matching lines of every input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Lines,
+    // The number of matching lines.
+    Count,
+    // The paths of inputs with at least one matching line.
+    FilesWithMatches,
+    // The paths of inputs without any matching lines.
+    FilesWithoutMatch,
+    // Nothing, the exit status tells whether anything matched.
+    Quiet,
 }
 
 // Implement the parser function as a Config::build() function instead.
-// The Error variant of the Result enum is a reference a string with a static lifetime.
+// The Error variant of the Result enum names the argument that was wrong.
 impl Config {
     // Note that 'where' notation for trait bounds could also be used.
     // mut must be specified since we'll be mutating args by iterating over it.
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, Error> {
         // First value in the vector will the name of our binary. Matches behavior of C programs. Lets programs use the name by which they were evoked in their execution.
         args.next();
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        // The defaults from the config file go first, so the command line
+        // overrides them.
+        let mut args: Vec<String> = args.collect();
+        // The subcommand must come first, before any options.
+        let command = match args.first().map(String::as_str) {
+            Some("index") => {
+                args.remove(0);
+                Command::Index
+            }
+            _ => Command::Search,
+        };
+        let no_config = args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--no-config");
+        let mut all_args = match no_config {
+            true => Vec::new(),
+            false => args::config_file_args()?,
         };
+        all_args.extend(args);
+
+        // Options may appear anywhere, the remaining arguments are positional.
+        let mut parser = Parser::new(all_args.into_iter());
+        let mut use_regex = false;
+        let mut fuzzy = None;
+        let mut patterns = Vec::new();
+        let mut ignore_case = None;
+        let mut smart_case = false;
+        let mut types = Types::default();
+        let mut selected_types = Vec::new();
+        let mut negated_types = Vec::new();
+        let mut globs = Vec::new();
+        let mut walk = WalkOptions::default();
+        let mut print = PrintOptions::default();
+        let mut invert = false;
+        let mut max_count = None;
+        let mut mode = OutputMode::Lines;
+        let mut whole_word = false;
+        let mut whole_line = false;
+        let mut text = false;
+        let mut multiline = false;
+        let mut search_zip = false;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut diff = false;
+        let mut threads = 0;
+        let mut sort_by_path = false;
+        let mut use_index = false;
+        // Whether -e or -f was given, even if -f named an empty file.
+        let mut patterns_given = false;
+        let mut positional = Vec::new();
+        // -A and -B take precedence over -C whatever the order they are given in.
+        let mut context = None;
+        let mut after_context = None;
+        let mut before_context = None;
+        let mut color = ColorChoice::Auto;
+        let mut colors = ColorScheme::default();
+
+        while let Some(arg) = parser.next_arg()? {
+            let option = match arg {
+                Arg::Value(value) => {
+                    positional.push(value);
+                    continue;
+                }
+                Arg::Option(option) => option,
+            };
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
+            match option.as_str() {
+                "-e" | "--regexp" => {
+                    patterns.push(parser.value()?);
+                    patterns_given = true;
+                }
+                "-f" | "--file" => {
+                    let path = parser.value()?;
+                    patterns_given = true;
+                    let contents = match fs::read_to_string(&path) {
+                        Ok(contents) => contents,
+                        Err(err) => {
+                            let message = err.to_string();
+                            return Err(ArgsError::PatternFile { path, message }.into());
+                        }
+                    };
+                    patterns.extend(contents.lines().map(String::from));
+                }
+                "-E" | "--regex" => use_regex = true,
+                "--fuzzy" => fuzzy = Some(parser.number()?),
+                "-w" | "--word-regexp" => whole_word = true,
+                "-x" | "--line-regexp" => whole_line = true,
+                "-a" | "--text" => text = true,
+                "-U" | "--multiline" => multiline = true,
+                "-z" | "--search-zip" => search_zip = true,
+                "--replace" => replace = Some(parser.value()?),
+                "--in-place" => in_place = true,
+                "--diff" => diff = true,
+                // The last of -i, -s and -S given decides.
+                "-i" | "--ignore-case" => (ignore_case, smart_case) = (Some(true), false),
+                "-s" | "--case-sensitive" => (ignore_case, smart_case) = (Some(false), false),
+                "-S" | "--smart-case" => (ignore_case, smart_case) = (None, true),
+                // Directories are always searched recursively, -r is accepted for grep users.
+                "-r" | "--recursive" => {}
+                "--follow" => walk.follow_links = true,
+                "--hidden" => walk.hidden = true,
+                "--no-ignore" => walk.no_ignore = true,
+                "--max-depth" => walk.max_depth = Some(parser.number()?),
+                "-t" | "--type" => selected_types.push(parser.value()?),
+                "-T" | "--type-not" => negated_types.push(parser.value()?),
+                "--type-add" => {
+                    let value = parser.value()?;
+                    if let Err(expected) = types.add(&value) {
+                        return Err(parser.invalid(value, expected).into());
+                    }
+                }
+                "-g" | "--glob" => globs.push(parser.value()?),
+                "-n" | "--line-number" => print.line_number = true,
+                "--column" => print.column = true,
+                "-b" | "--byte-offset" => print.byte_offset = true,
+                "--vimgrep" => print.vimgrep = true,
+                "--json" => print.json = true,
+                "-v" | "--invert-match" => invert = true,
+                "-c" | "--count" => mode = OutputMode::Count,
+                "-l" | "--files-with-matches" => mode = OutputMode::FilesWithMatches,
+                "-L" | "--files-without-match" => mode = OutputMode::FilesWithoutMatch,
+                "-q" | "--quiet" => mode = OutputMode::Quiet,
+                "-m" | "--max-count" => max_count = Some(parser.number()?),
+                "-A" | "--after-context" => after_context = Some(parser.number()?),
+                "-B" | "--before-context" => before_context = Some(parser.number()?),
+                "-C" | "--context" => context = Some(parser.number()?),
+                "--color" => {
+                    let value = parser.value()?;
+                    color = match ColorChoice::parse(&value) {
+                        Some(choice) => choice,
+                        None => return Err(parser.invalid(value, "auto, always or never").into()),
+                    }
+                }
+                "--colors" => {
+                    let value = parser.value()?;
+                    colors = match ColorScheme::parse(&value) {
+                        Ok(colors) => colors,
+                        Err(expected) => return Err(parser.invalid(value, expected).into()),
+                    }
+                }
+                "-j" | "--threads" => threads = parser.number()?,
+                "--sort" => {
+                    let value = parser.value()?;
+                    sort_by_path = match value.as_str() {
+                        "path" => true,
+                        "none" => false,
+                        _ => return Err(parser.invalid(value, "path or none").into()),
+                    }
+                }
+                "--index" => use_index = true,
+                // Already handled before parsing.
+                "--no-config" => {}
+                "-h" | "--help" => return Err(ArgsError::Help.into()),
+                "-V" | "--version" => return Err(ArgsError::Version.into()),
+                _ => return Err(ArgsError::UnknownOption(option).into()),
+            }
+        }
+        // Types are looked up once all the options are read, so --type-add
+        // may come after the --type using it.
+        walk.filter = match FileFilter::new(&types, &selected_types, &negated_types, &globs) {
+            Ok(filter) => filter,
+            Err(name) => {
+                let option = match selected_types.contains(&name) {
+                    true => "--type",
+                    false => "--type-not",
+                };
+                return Err(ArgsError::InvalidValue {
+                    option: option.to_string(),
+                    value: name,
+                    expected: String::from("a built-in file type or one added with --type-add"),
+                }
+                .into());
+            }
         };
+        print.after_context = after_context.or(context).unwrap_or(0);
+        print.before_context = before_context.or(context).unwrap_or(0);
+        print.colors = color.enabled().then_some(colors);
+
+        // Without -e or -f, the first positional argument is the query.
+        // Indexing takes no query, only directories.
+        let mut args = positional.into_iter();
+        if !patterns_given && command == Command::Search {
+            match args.next() {
+                Some(arg) => patterns.push(arg),
+                None => return Err(ArgsError::MissingQuery.into()),
+            }
+        }
+
+        let paths: Vec<String> = args.collect();
 
         // The .is_ok() returns true if the Result enum is Ok and False if it is Err. Unwraps Result.
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // The -i, -s and -S flags take precedence over the environment variable.
+        // With --smart-case, the case only matters when a pattern has uppercase letters.
+        let ignore_case = ignore_case.unwrap_or_else(|| match smart_case {
+            true => !patterns
+                .iter()
+                .any(|pattern| has_uppercase(pattern, use_regex)),
+            false => env::var("IGNORE_CASE").is_ok(),
+        });
+
+        // Compile the pattern up front so an invalid pattern is reported as a
+        // usage error instead of failing halfway through the search.
+        let regex = if use_regex {
+            let build = match (whole_line, whole_word) {
+                (true, _) => Regex::build_many_whole_line,
+                (false, true) => Regex::build_many_whole_word,
+                (false, false) => Regex::build_many,
+            };
+            match build(&patterns, ignore_case) {
+                Ok(regex) => Some(regex),
+                Err(err) => return Err(ArgsError::InvalidPattern(err).into()),
+            }
+        } else {
+            None
+        };
+
+        // JSON events are only printed for lines, not counts, paths or diffs.
+        if print.json {
+            let other = match mode {
+                OutputMode::Count => Some("--count"),
+                OutputMode::FilesWithMatches => Some("--files-with-matches"),
+                OutputMode::FilesWithoutMatch => Some("--files-without-match"),
+                _ if diff => Some("--diff"),
+                _ => None,
+            };
+            if let Some(other) = other {
+                let option = String::from("--json");
+                let other = other.to_string();
+                return Err(ArgsError::Conflict { option, other }.into());
+            }
+        }
+
+        for (option, given) in [("--in-place", in_place), ("--diff", diff)] {
+            if given && replace.is_none() {
+                let option = option.to_string();
+                return Err(ArgsError::Requires {
+                    option,
+                    requires: "--replace",
+                }
+                .into());
+            }
+        }
+        // The distance is from a single fixed string, on one line. Building
+        // an index doesn't search, so it has no query to check.
+        if fuzzy.is_some() && command == Command::Search {
+            for (other, given) in [("--regex", use_regex), ("--multiline", multiline)] {
+                if given {
+                    let option = String::from("--fuzzy");
+                    let other = other.to_string();
+                    return Err(ArgsError::Conflict { option, other }.into());
+                }
+            }
+            if patterns.len() > 1 {
+                let option = String::from("--fuzzy");
+                return Err(ArgsError::Requires {
+                    option,
+                    requires: "a single query",
+                }
+                .into());
+            }
+            let too_long = |query: &&String| query.chars().count() > fuzzy::MAX_QUERY_LEN;
+            if let Some(query) = patterns.first().filter(too_long) {
+                return Err(ArgsError::InvalidValue {
+                    option: String::from("--fuzzy"),
+                    value: query.clone(),
+                    expected: format!("a query of at most {} characters", fuzzy::MAX_QUERY_LEN),
+                }
+                .into());
+            }
+        }
+
+        // A replaced match would be cut up between the lines it covers.
+        if multiline && replace.is_some() {
+            let option = String::from("--multiline");
+            let other = String::from("--replace");
+            return Err(ArgsError::Conflict { option, other }.into());
+        }
+        let replace = replace.map(|text| Replacement::parse(&text));
+        // Without a regex there are no groups, only $0 for the whole match.
+        let groups = regex.as_ref().map_or(1, |regex| regex.captures_len());
+        if let Some(replacement) = &replace {
+            if replacement.max_group() >= groups {
+                return Err(ArgsError::InvalidValue {
+                    option: String::from("--replace"),
+                    value: format!("${}", replacement.max_group()),
+                    expected: String::from("a group of the pattern"),
+                }
+                .into());
+            }
+        }
 
         Ok(Config {
-            query,
-            file_path,
+            command,
+            patterns,
+            paths,
             ignore_case,
+            regex,
+            fuzzy,
+            walk,
+            print,
+            invert,
+            max_count,
+            mode,
+            whole_word,
+            whole_line,
+            text,
+            multiline,
+            search_zip,
+            replace,
+            in_place,
+            diff,
+            threads,
+            sort_by_path,
+            use_index,
         })
     }
 }
 
+// Whether the pattern has uppercase letters. In a regex the letter after a
+// backslash is left out, so \W or \S don't count.
+fn has_uppercase(pattern: &str, regex: bool) -> bool {
+    let mut escaped = false;
+    pattern.chars().any(|c| {
+        let skip = escaped;
+        escaped = regex && !escaped && c == '\\';
+        !skip && c.is_uppercase()
+    })
+}
+
 // Extract logic to separate function.
-// Box<dyn Error> specifies that the function will return a type that implements the Error trait.
-// It's a way of specifying that an error will be returned, without specifying which error.
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
-
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+// Returns whether anything matched, which decides the exit status like in grep.
+// An input that can't be searched is reported and the search goes on, but
+// then it fails with Error::Inputs at the end.
+pub fn run(config: Config) -> Result<bool, Error> {
+    if config.command == Command::Index {
+        index::run(&config)?;
+        return Ok(true);
+    }
+
+    // Like grep, only prefix lines with the file path when more than one file may be searched.
+    let with_path =
+        config.paths.len() > 1 || config.paths.iter().any(|path| Path::new(path).is_dir());
+    let finder = Finder::new(&config);
+    let start = Instant::now();
+
+    // Searching a single input streams straight to stdout, otherwise the
+    // files are spread over a pool of threads.
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
+    };
+    if with_path && threads > 1 {
+        return parallel::run(&config, &finder, threads, with_path, start);
+    }
+
+    let stdout = Stdout {
+        out: io::stdout().lock(),
+        failed: false,
+    };
+    let mut printer = Printer::new(stdout, &config.print, with_path);
+    let mut matched = false;
+    let mut errors = 0;
+    for input in inputs(&config) {
+        let path = match input {
+            Ok(path) => path,
+            Err(err) => {
+                print_error(&err);
+                errors += 1;
+                continue;
+            }
+        };
+        match search_path(&config, &finder, &path, &mut printer) {
+            Ok(found) => matched |= found,
+            Err(err) if printer.get_ref().failed => return Err(Error::Output(err)),
+            Err(err) => {
+                print_error(&input_error(&path, err));
+                errors += 1;
+            }
+        }
+
+        // Nothing more is printed, so the first match settles the exit status.
+        if matched && config.mode == OutputMode::Quiet {
+            return Ok(true);
+        }
+    }
+
+    if config.print.json {
+        let stats = printer.stats();
+        printer::print_summary(printer.into_inner(), &stats, start.elapsed())
+            .map_err(Error::Output)?;
+    }
+    match errors {
+        0 => Ok(matched),
+        errors => Err(Error::Inputs(errors)),
+    }
+}
+
+// Standard output, remembering whether a write failed. Searching an input
+// fails with an io::Error both when reading the input and when writing its
+// results, and only the first lets the search go on.
+struct Stdout {
+    out: io::StdoutLock<'static>,
+    failed: bool,
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf);
+        self.failed |= written.is_err();
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let flushed = self.out.flush();
+        self.failed |= flushed.is_err();
+        flushed
+    }
+}
+
+// The error of an input that couldn't be searched. io::Error doesn't say
+// which file failed, so the path is added.
+fn input_error(path: &Path, err: io::Error) -> Error {
+    match path == Path::new("-") {
+        true => Error::io(Path::new(STDIN_NAME), err),
+        false => Error::io(path, err),
+    }
+}
+
+// Reports an input that couldn't be searched, while the others still are.
+fn print_error(err: &Error) {
+    eprintln!("minigrep: {err}");
+}
+
+// Every file to search, in order. Standard input is the path "-", which is
+// also searched when no paths are given.
+fn inputs(config: &Config) -> impl Iterator<Item = Result<PathBuf, Error>> + '_ {
+    let stdin = config.paths.is_empty().then(|| String::from("-"));
+    config.paths.iter().cloned().chain(stdin).flat_map(
+        |path| -> Box<dyn Iterator<Item = Result<PathBuf, Error>>> {
+            if path == "-" {
+                return Box::new(iter::once(Ok(PathBuf::from(path))));
+            }
+            if config.use_index && Path::new(&path).is_dir() {
+                match indexed_inputs(config, Path::new(&path)) {
+                    Ok(Some(candidates)) => return Box::new(candidates.into_iter()),
+                    Ok(None) => {}
+                    // An index that can't be read is reported, and every
+                    // file is searched instead.
+                    Err(err) => {
+                        let walk = Walk::new(path, &config.walk);
+                        return Box::new(iter::once(Err(err)).chain(walk));
+                    }
+                }
+            }
+            Box::new(Walk::new(path, &config.walk))
+        },
+    )
+}
+
+// The files of a directory that may match according to its index. None
+// when they all have to be searched, because the index is missing or out of
+// date or can't help with this search.
+fn indexed_inputs(
+    config: &Config,
+    dir: &Path,
+) -> Result<Option<Vec<Result<PathBuf, Error>>>, Error> {
+    let Some(required) = index::required_trigrams(config) else {
+        return Ok(None);
+    };
+    let index = match index::Index::load(dir)? {
+        Some(index) => index,
+        None => {
+            eprintln!("{}: not indexed, searching every file", dir.display());
+            return Ok(None);
+        }
+    };
+    let candidates = index.candidates(&config.walk, &required);
+    if candidates.is_none() {
+        eprintln!(
+            "{}: changed since it was indexed, searching every file",
+            dir.display()
+        );
+    }
+    Ok(candidates)
+}
+
+// Searches one input and prints its results. Returns whether it counts as
+// a success for the exit status.
+fn search_path<W: Write>(
+    config: &Config,
+    finder: &Finder,
+    path: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<bool> {
+    // --diff and --in-place work on whole files instead of printing the replaced lines.
+    let rewrite = config.in_place || config.diff;
+    if let Some(replacement) = config.replace.as_ref().filter(|_| rewrite) {
+        if path == Path::new("-") {
+            let message = "can't be rewritten, only files can";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let count = replace::rewrite(config, finder, replacement, path, printer)?;
+        return report(config, path, count, printer);
+    }
+
+    if path == Path::new("-") {
+        let stdin = Path::new(STDIN_NAME);
+        let count = search_reader(config, finder, io::stdin().lock(), stdin, printer)?;
+        return report(config, stdin, count, printer);
+    }
+
+    let file = File::open(path)?;
+    // Compressed files are always read through the decompressor.
+    let metadata = file.metadata()?;
+    if metadata.is_file() && metadata.len() >= MMAP_THRESHOLD && !config.search_zip {
+        let map = Mmap::map(&file)?;
+        let count = search_slice(config, finder, &map, path, printer)?;
+        return report(config, path, count, printer);
+    }
+
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, file);
+    let format = match config.search_zip {
+        true => reader.fill_buf().map(Format::detect).unwrap_or(None),
+        false => None,
+    };
+    let count = match format {
+        Some(format) => search_compressed(config, finder, format, path, printer),
+        None => search_reader(config, finder, reader, path, printer),
+    };
+    report(config, path, count?, printer)
+}
+
+// Searches the decompressed contents of the file, for --search-zip.
+fn search_compressed<W: Write>(
+    config: &Config,
+    finder: &Finder,
+    format: Format,
+    path: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let mut decompressor = decompress::spawn(format, path)?;
+    let reader = BufReader::with_capacity(READ_BUFFER_SIZE, decompressor.stdout());
+
+    // The child is waited for even when the search fails, the reader and so
+    // the pipe are dropped by then.
+    let count = search_reader(config, finder, reader, path, printer);
+    decompressor.finish()?;
+    count
+}
+
+// Prints the per input results of the count and file list modes. Returns
+// whether the input counts as a success for the exit status.
+fn report<W: Write>(
+    config: &Config,
+    path: &Path,
+    count: usize,
+    printer: &mut Printer<W>,
+) -> io::Result<bool> {
+    printer.end_file(path)?;
+    match config.mode {
+        OutputMode::Count => printer.print_count(path, count)?,
+        OutputMode::FilesWithMatches if count > 0 => printer.print_path(path)?,
+        // Like GNU grep, listing a file is what counts as success for -L.
+        OutputMode::FilesWithoutMatch => {
+            if count == 0 {
+                printer.print_path(path)?;
+            }
+            return Ok(count == 0);
+        }
+        _ => {}
+    }
+    Ok(count > 0)
+}
+
+// Searches the input one line at a time and prints its results. Returns
+// the number of matching lines.
+pub(crate) fn search_reader<W: Write>(
+    config: &Config,
+    finder: &Finder,
+    mut reader: impl BufRead,
+    path: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    // Like grep, a NUL byte near the start marks the input as binary. Its
+    // lines are searched all the same, but only a note is printed for them.
+    let binary = !config.text && is_binary(reader.fill_buf()?);
+    let searcher = searcher(config, binary);
+    searcher.search_reader(
+        finder,
+        reader,
+        path,
+        &mut Output::new(config, printer, binary),
+    )
+}
+
+// Searches a whole file in memory, the same as search_reader.
+fn search_slice<W: Write>(
+    config: &Config,
+    finder: &Finder,
+    contents: &[u8],
+    path: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let binary = !config.text && is_binary(&contents[..contents.len().min(READ_BUFFER_SIZE)]);
+    let searcher = searcher(config, binary);
+    searcher.search_slice(
+        finder,
+        contents,
+        path,
+        &mut Output::new(config, printer, binary),
+    )
+}
+
+fn searcher(config: &Config, binary: bool) -> Searcher {
+    // Context is only printed around lines, and not with --vimgrep.
+    let context = config.mode == OutputMode::Lines && !binary && !config.print.vimgrep;
+    let (before_context, after_context) = match context {
+        true => (config.print.before_context, config.print.after_context),
+        false => (0, 0),
     };
+    Searcher {
+        invert: config.invert,
+        max_count: config.max_count,
+        before_context,
+        after_context,
+        multiline: config.multiline,
+    }
+}
+
+// Passes the lines the searcher selects on to the printer, as the output
+// mode asks for them.
+struct Output<'a, W: Write> {
+    config: &'a Config,
+    printer: &'a mut Printer<W>,
+    // Binary inputs only get a note that they match.
+    binary: bool,
+}
+
+impl<'a, W: Write> Output<'a, W> {
+    fn new(config: &'a Config, printer: &'a mut Printer<W>, binary: bool) -> Output<'a, W> {
+        printer.begin_file();
+        Output {
+            config,
+            printer,
+            binary,
+        }
+    }
+}
+
+impl<W: Write> Sink for Output<'_, W> {
+    fn matched(&mut self, path: &Path, found: &Match) -> io::Result<bool> {
+        match self.config.mode {
+            OutputMode::Lines if self.binary => {
+                self.printer.print_binary(path)?;
+                Ok(false)
+            }
+            OutputMode::Lines => match &self.config.replace {
+                Some(replacement) => {
+                    let regex = self.config.regex.as_ref();
+                    let (line, spans) = replacement.apply(found.line, &found.spans, regex);
+                    let replaced = Match {
+                        line: &line,
+                        spans,
+                        distances: found.distances.clone(),
+                        ..*found
+                    };
+                    self.printer.matched(path, &replaced)
+                }
+                None => self.printer.matched(path, found),
+            },
+            OutputMode::Count => Ok(true),
+            // One match is enough to decide these.
+            _ => Ok(false),
+        }
+    }
+
+    fn context(&mut self, path: &Path, line: &Match) -> io::Result<()> {
+        self.printer.context(path, line)
+    }
+}
+
+// Whether a block from the start of an input looks like binary data.
+fn is_binary(block: &[u8]) -> bool {
+    block.contains(&0)
+}
+
+// The matcher picked from the options in config.
+pub(crate) struct Finder<'a> {
+    // Shared by the threads searching files.
+    matcher: Box<dyn Matcher + Send + Sync + 'a>,
+    // Only keep matches that are whole words, or the whole line.
+    whole_word: bool,
+    whole_line: bool,
+}
+
+impl<'a> Finder<'a> {
+    fn new(config: &'a Config) -> Finder<'a> {
+        let matcher: Box<dyn Matcher + Send + Sync + 'a> = if let Some(regex) = &config.regex {
+            Box::new(regex)
+        } else if config.patterns.len() != 1 {
+            // None at all, from an empty -f file, match nothing.
+            Box::new(MultiPattern::new(&config.patterns, config.ignore_case))
+        } else if let Some(max_distance) = config.fuzzy {
+            let fuzzy = Fuzzy::new(&config.patterns[0], max_distance, config.ignore_case);
+            Box::new(fuzzy.expect("the query length is checked by Config::build"))
+        } else if config.ignore_case {
+            Box::new(CaseInsensitive::new(&config.patterns[0]))
+        } else {
+            Box::new(Substring::new(&config.patterns[0]))
+        };
+
+        Finder {
+            matcher,
+            whole_word: config.whole_word,
+            whole_line: config.whole_line,
+        }
+    }
+}
+
+impl Matcher for Finder<'_> {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut spans = self.matcher.find_iter(line);
+        // With -x or -w, a regex is compiled with the line or word edges
+        // around its patterns, so its matches already line up with them.
+        // Fixed strings are checked here instead: the match must start and
+        // end at a line edge, or have no word character on either side.
+        // With --multiline, line holds many lines and a match may span several.
+        if self.whole_line {
+            spans.retain(|(start, end)| {
+                let starts_line = line[..*start].is_empty() || line[..*start].ends_with('\n');
+                let rest = &line[*end..];
+                starts_line
+                    && (rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n"))
+            });
+        }
+        if self.whole_word {
+            spans.retain(|(start, end)| {
+                let before = line[..*start].chars().next_back();
+                let after = line[*end..].chars().next();
+                !before.is_some_and(regex::is_word_char) && !after.is_some_and(regex::is_word_char)
+            });
+        }
+        spans
+    }
+
+    // Whole word and line matches still hold the literal.
+    fn literal(&self) -> Option<&str> {
+        self.matcher.literal()
+    }
 
-    for line in results {
-        println!("{line}");
+    fn distance(&self, found: &str) -> Option<usize> {
+        self.matcher.distance(found)
     }
+}
 
-    Ok(())
+// A line of contents holding at least one match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    // Line numbers start at 1, like in an editor.
+    pub line_number: usize,
+    // The byte offset of the start of the line within contents.
+    pub byte_offset: usize,
+    pub line: &'a str,
+    // Byte ranges of every match within line, from left to right.
+    pub spans: Vec<(usize, usize)>,
+    // The edit distance of every match from the query with --fuzzy, empty
+    // for exact matches.
+    pub distances: Vec<usize>,
 }
 
-// The results is a vector of string slices, the string from which the slice
+// The results is a vector of matches holding string slices, the string from which the slice
 // is taken must be valid for the slice to be. Therefor, the result of search
 // is defined to have the same lifetime as contents.
 // If we sliced from a string in memory, we could use a static lifetime instead
 // since the string would not be invalid once contents go out of scope.
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    return contents
-        .lines()
-        .filter(|line| line.contains(query))
-        .collect();
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(&Substring::new(query), contents)
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(&CaseInsensitive::new(query), contents)
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+// Searches for several fixed strings at once.
+pub fn search_multiple<'a>(automaton: &AhoCorasick, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(automaton, contents)
+}
+
+// Case sensitivity is decided when the regex is compiled.
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(regex, contents)
+}
+
+// Runs the matcher on every line of contents, keeping the lines where it
+// finds something. A Searcher does the same for inputs read as a stream.
+pub fn search_matcher<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<Match<'a>> {
+    // Only the lines holding the literal can match, so the others are skipped
+    // without splitting contents into lines.
+    if let Some(literal) = matcher.literal() {
+        return literal::Lines::new(contents.as_bytes(), literal.as_bytes())
+            .filter_map(|(line_number, start, end)| {
+                let line = &contents[start..end];
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                let spans = matcher.find_iter(line);
+                (!spans.is_empty()).then(|| Match {
+                    line_number,
+                    byte_offset: start,
+                    line,
+                    distances: sink::distances(matcher, line, &spans),
+                    spans,
+                })
+            })
+            .collect();
+    }
+    lines(contents)
+        .enumerate()
+        .filter_map(|(index, (byte_offset, line))| {
+            let spans = matcher.find_iter(line);
+            if spans.is_empty() {
+                return None;
+            }
+            Some(Match {
+                line_number: index + 1,
+                byte_offset,
+                line,
+                distances: sink::distances(matcher, line, &spans),
+                spans,
+            })
+        })
+        .collect()
+}
 
-    return contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query))
-        .collect();
+// Like str::lines, but also yields the byte offset where each line starts.
+fn lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Some((start, line.strip_suffix('\r').unwrap_or(line)))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn lines_of(matches: Vec<Match<'_>>) -> Vec<&str> {
+        matches.into_iter().map(|found| found.line).collect()
+    }
+
     #[test]
     fn case_sensitive() {
         let query = "duct";
@@ -92,7 +988,10 @@ safe, fast, productive.
 Pick three.
 Duct tape.";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            lines_of(search(query, contents))
+        );
     }
 
     #[test]
@@ -106,7 +1005,407 @@ Trust me.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            lines_of(search_case_insensitive(query, contents))
+        );
+    }
+
+    #[test]
+    fn match_positions() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nTRUST the rust.";
+
+        assert_eq!(
+            vec![
+                Match {
+                    line_number: 1,
+                    byte_offset: 0,
+                    line: "Rust:",
+                    spans: vec![(0, 4)],
+                    distances: Vec::new(),
+                },
+                Match {
+                    line_number: 3,
+                    byte_offset: 31,
+                    line: "TRUST the rust.",
+                    spans: vec![(1, 5), (10, 14)],
+                    distances: Vec::new(),
+                },
+            ],
+            search_case_insensitive("rust", contents)
+        );
+
+        // 'İ' lowercases to two characters, which must not shift the spans.
+        let found = search_case_insensitive("x", "İx");
+        assert_eq!(vec![(2, 3)], found[0].spans);
+    }
+
+    #[test]
+    fn streams_lines() {
+        let config = Config::build(["minigrep", "fast"].map(String::from).into_iter()).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config.print, false);
+        let input: &[u8] = b"Rust:\r\nsafe, \xff fast\nPick three.";
+
+        let finder = Finder::new(&config);
+        let count = search_reader(&config, &finder, input, Path::new("-"), &mut printer).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(
+            "safe, \u{FFFD} fast\n",
+            String::from_utf8(printer.into_inner()).unwrap()
         );
     }
+
+    fn search_input(args: &[&str], input: &str) -> (usize, String) {
+        let args = ["minigrep"].iter().chain(args).map(|arg| arg.to_string());
+        let config = Config::build(args).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config.print, false);
+
+        let finder = Finder::new(&config);
+        let path = Path::new("-");
+        let count = search_reader(&config, &finder, input.as_bytes(), path, &mut printer).unwrap();
+        (count, String::from_utf8(printer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn case_folding_and_smart_case() {
+        let input = "STRAẞE\nstrasse\nİstanbul\nistanbul\nσοφός";
+        let output = |count, lines: &str| (count, lines.to_string());
+
+        assert_eq!(
+            output(1, "STRAẞE\n"),
+            search_input(&["-i", "straße"], input)
+        );
+        assert_eq!(output(1, "İstanbul\n"), search_input(&["-i", "İST"], input));
+        assert_eq!(output(1, "istanbul\n"), search_input(&["-i", "IST"], input));
+        assert_eq!(output(1, "σοφός\n"), search_input(&["-i", "ΣΟΦΌΣ"], input));
+        assert_eq!(
+            output(2, "STRAẞE\nσοφός\n"),
+            search_input(&["-i", "-e", "straße", "-e", "ΣΟΦΌΣ"], input)
+        );
+
+        assert_eq!(
+            output(2, "İstanbul\nistanbul\n"),
+            search_input(&["-S", "stanbul"], input)
+        );
+        assert_eq!(output(0, ""), search_input(&["-S", "Stanbul"], input));
+        // Escapes like \S are not uppercase letters.
+        assert!(!has_uppercase(r"\S+\w", true));
+        assert!(has_uppercase(r"\S+\w", false));
+        assert!(has_uppercase(r"\\S", true));
+    }
+
+    #[test]
+    fn whole_word_and_line() {
+        let input = "productive duct\nDuct_tape\nduct\nDUCT-work\nÆduct";
+        let output = |count, lines: &str| (count, lines.to_string());
+
+        assert_eq!(
+            output(2, "productive duct\nduct\n"),
+            search_input(&["-w", "duct"], input)
+        );
+        assert_eq!(
+            output(3, "productive duct\nduct\nDUCT-work\n"),
+            search_input(&["-wi", "duct"], input)
+        );
+        assert_eq!(output(1, "duct\n"), search_input(&["-x", "duct"], input));
+        assert_eq!(
+            output(2, "Duct_tape\nduct\n"),
+            search_input(&["-xi", "-e", "duct", "-e", "duct_tape"], input)
+        );
+        // The whole line must match, not just the first alternative.
+        assert_eq!(
+            output(1, "duct\n"),
+            search_input(&["-xE", "du|duct"], input)
+        );
+        // Same for a whole word.
+        assert_eq!(
+            output(2, "productive duct\nduct\n"),
+            search_input(&["-wE", "du|duct"], input)
+        );
+    }
+
+    #[test]
+    fn multiline() {
+        let input = "#[test]\nfn one() {}\n\n#[test]\r\nfn two() {\n}\nlast";
+        let output = |count, lines: &str| (count, lines.to_string());
+
+        // Every line of the match is printed with its own number.
+        assert_eq!(
+            output(2, "4:#[test]\n5:fn two() {\n"),
+            search_input(&["-Un", "-E", r"test\]\s+fn t"], input)
+        );
+        assert_eq!(
+            output(0, ""),
+            search_input(&["-E", r"test\]\s+fn t"], input)
+        );
+        assert_eq!(
+            output(2, "fn two() {\n}\n"),
+            search_input(&["-Ux", "fn two() {\n}"], input)
+        );
+        // ^ and $ still anchor to lines, and . doesn't cross them.
+        assert_eq!(
+            output(2, "fn one() {}\nfn two() {\n"),
+            search_input(&["-U", "-E", r"^fn.*$"], input)
+        );
+        assert_eq!(
+            output(1, "#[test]\n"),
+            search_input(&["-Uv", "-m1", "-E", r"\{\n?}?|^$|last"], input)
+        );
+        assert!(Config::build(
+            ["minigrep", "-U", "--replace", "x", "q"]
+                .map(String::from)
+                .into_iter()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn fuzzy() {
+        let input = "let config = 1;\nlet confog = 2;\nlet cnofgi = 3;\nCONFIG confg";
+        let output = |count, lines: &str| (count, lines.to_string());
+
+        // The distance of every match follows the line number.
+        assert_eq!(
+            output(
+                3,
+                "1:d=0:let config = 1;\n2:d=1:let confog = 2;\n4:d=1:CONFIG confg\n"
+            ),
+            search_input(&["-n", "--fuzzy", "1", "config"], input)
+        );
+        assert_eq!(
+            output(1, "d=0:CONFIG confg\n"),
+            search_input(&["-i", "--fuzzy", "1", "-x", "config confg"], input)
+        );
+        assert!(search_input(&["--json", "--fuzzy=2", "cnofig"], input)
+            .1
+            .contains(r#""match":"cnofg","start":4,"end":9,"distance":1"#));
+
+        // Library callers get the distances as well.
+        let fuzzy = Fuzzy::new("config", 1, false).unwrap();
+        let found = search_matcher(&fuzzy, input);
+        let distances: Vec<&[usize]> = found.iter().map(|m| &m.distances[..]).collect();
+        assert_eq!(vec![&[0][..], &[1], &[1]], distances);
+
+        let build = |args: &[&str]| {
+            let args = ["minigrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::build(args).err().map(|err| err.to_string())
+        };
+        assert_eq!(
+            Some(String::from("option '--fuzzy' requires a single query")),
+            build(&["--fuzzy", "1", "-e", "a", "-e", "b"])
+        );
+        assert!(build(&["--fuzzy", "1", "-E", "a"]).is_some());
+        assert_eq!(
+            Some(String::from(
+                "invalid value 'xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx' \
+                 for option '--fuzzy': expected a query of at most 64 characters"
+            )),
+            build(&["--fuzzy", "1", &"x".repeat(65)])
+        );
+        assert_eq!(
+            output(
+                3,
+                "-:1:5:d=1:let config = 1;\n-:2:5:d=0:let confog = 2;\n\
+                 -:4:1:d=1:CONFIG confg\n-:4:8:d=1:CONFIG confg\n"
+            ),
+            search_input(&["--vimgrep", "-i", "--fuzzy", "1", "confog"], input)
+        );
+        // Building an index has no query for the distance.
+        assert_eq!(None, build(&["index", "--fuzzy", "1", "."]));
+        assert_eq!(None, build(&["--fuzzy", "1", "--index", "config", "."]));
+    }
+
+    #[test]
+    fn binary() {
+        let input = "one\0\ntwo\none";
+        assert_eq!(
+            (1, String::from("Binary file - matches\n")),
+            search_input(&["one"], input)
+        );
+        assert_eq!((2, String::new()), search_input(&["-c", "one"], input));
+        assert_eq!(
+            (2, String::from("one\0\none\n")),
+            search_input(&["--text", "one"], input)
+        );
+    }
+
+    #[test]
+    fn replace() {
+        let input = "fn one(a: u8)\nlet x = one(2);\nnone";
+        assert_eq!(
+            (3, String::from("fn two(a: u8)\nlet x = two(2);\nntwo\n")),
+            search_input(&["--replace", "two", "one"], input)
+        );
+        assert_eq!(
+            (1, String::from("let x = one[2];\n")),
+            search_input(&["-E", "--replace", "$1[$2]", r"(\w+)\((\d)\)"], input)
+        );
+
+        let args = ["minigrep", "--in-place", "one"].map(String::from);
+        match Config::build(args.into_iter()) {
+            Err(err) => assert_eq!("option '--in-place' requires --replace", err.to_string()),
+            Ok(_) => panic!("--in-place was accepted without --replace"),
+        }
+    }
+
+    #[test]
+    fn invert_and_max_count() {
+        let input = "one\ntwo\nthree\nfour\nfive";
+        let output = |count, lines: &str| (count, lines.to_string());
+
+        assert_eq!(
+            output(2, "three\nfive\n"),
+            search_input(&["-v", "o"], input)
+        );
+        assert_eq!(
+            output(2, "one\nthree\n"),
+            search_input(&["-m", "2", "e"], input)
+        );
+        // The after context of the last match is still printed.
+        assert_eq!(
+            output(1, "one\ntwo\n"),
+            search_input(&["-m", "1", "-A", "1", "o"], input)
+        );
+        assert_eq!(output(3, ""), search_input(&["-c", "o"], input));
+        // Listing files stops at the first match.
+        assert_eq!(output(1, ""), search_input(&["-l", "e"], input));
+    }
+
+    #[test]
+    fn regex() {
+        let regex = Regex::build(r"^\w+:$|fast,", false).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive."],
+            lines_of(search_regex(&regex, contents))
+        );
+    }
+
+    #[test]
+    fn build_invalid_regex() {
+        let args = ["minigrep", "--regex", "(unclosed", "poem.txt"].map(String::from);
+
+        match Config::build(args.into_iter()) {
+            Err(err) => assert_eq!(
+                "Invalid regular expression: unclosed group at position 0",
+                err.to_string()
+            ),
+            Ok(_) => panic!("invalid pattern was accepted"),
+        }
+    }
+
+    #[test]
+    fn build_paths_and_walk_options() {
+        let args = [
+            "minigrep",
+            "--max-depth",
+            "2",
+            "to",
+            "src",
+            "--follow",
+            "poem.txt",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert_eq!(vec!["to"], config.patterns);
+        assert_eq!(vec!["src", "poem.txt"], config.paths);
+        assert_eq!(Some(2), config.walk.max_depth);
+        assert!(config.walk.follow_links);
+        assert!(!config.walk.hidden);
+    }
+
+    #[test]
+    fn build_flags() {
+        let args = ["minigrep", "-inv", "-m2", "--", "-query", "-"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.ignore_case);
+        assert!(config.print.line_number);
+        assert!(config.invert);
+        assert_eq!(Some(2), config.max_count);
+        assert_eq!(vec!["-query"], config.patterns);
+        assert_eq!(vec!["-"], config.paths);
+        assert_eq!(0, config.threads);
+        assert!(!config.sort_by_path);
+
+        let args = ["minigrep", "-j4", "--sort=path", "query"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(4, config.threads);
+        assert!(config.sort_by_path);
+
+        let args = ["minigrep", "-nk", "query"].map(String::from);
+        match Config::build(args.into_iter()) {
+            Err(err) => assert_eq!("unknown option '-k'", err.to_string()),
+            Ok(_) => panic!("unknown option was accepted"),
+        }
+    }
+
+    #[test]
+    fn build_patterns() {
+        let args = ["minigrep", "-e", "one", "--regexp=two", "poem.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["one", "two"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.paths);
+
+        let args = ["minigrep", "-f", "missing-patterns.txt", "poem.txt"].map(String::from);
+        match Config::build(args.into_iter()) {
+            Err(Error::Args(ArgsError::PatternFile { path, .. })) => {
+                assert_eq!("missing-patterns.txt", path)
+            }
+            _ => panic!("missing pattern file was accepted"),
+        }
+
+        // An empty pattern file has no patterns, which match nothing, and
+        // the path after it is still a path.
+        let empty = env::temp_dir().join(format!("minigrep-empty-{}", std::process::id()));
+        fs::write(&empty, "").unwrap();
+        let empty = empty.to_str().unwrap();
+        let config = Config::build(
+            ["minigrep", "-f", empty, "f.txt"]
+                .map(String::from)
+                .into_iter(),
+        );
+        assert_eq!(vec!["f.txt"], config.unwrap().paths);
+        for flags in [&[][..], &["-E"], &["-i"], &["--fuzzy", "1"]] {
+            let args: Vec<&str> = flags.iter().copied().chain(["-f", empty]).collect();
+            assert_eq!((0, String::new()), search_input(&args, "one\ntwo"));
+        }
+        assert_eq!(
+            (2, String::from("one\ntwo\n")),
+            search_input(&["-v", "-f", empty], "one\ntwo")
+        );
+        fs::remove_file(empty).unwrap();
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        let (count, output) = search_input(&["-i", "-e", "THREE", "-e", "fiv"], "one\nthree\nFive");
+        assert_eq!(2, count);
+        assert_eq!("three\nFive\n", output);
+
+        let automaton = AhoCorasick::new(&["Pick", "tape"]);
+        let contents = "Pick three.\nDuct tape.\nTrust me.";
+        assert_eq!(
+            vec!["Pick three.", "Duct tape."],
+            lines_of(search_multiple(&automaton, contents))
+        );
+    }
+
+    #[test]
+    fn build_context() {
+        let args = ["minigrep", "-A", "1", "-C", "3", "to", "poem.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(1, config.print.after_context);
+        assert_eq!(3, config.print.before_context);
+
+        let args = ["minigrep", "--context=2", "to", "poem.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(2, config.print.after_context);
+
+        let args = ["minigrep", "-C", "x", "to", "poem.txt"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
 }
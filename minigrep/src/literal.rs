@@ -0,0 +1,235 @@
+// Finding a fixed string in a large buffer quickly, for searching whole
+// files at once. Rather than looking at every line, the search jumps from
+// one occurrence of the string to the next and only then finds the line
+// around it.
+//
+// Candidates are found many positions at a time: at every position the
+// first and the last byte of the needle are compared, and only where both
+// match is the needle compared in full. On x86_64 that is 16 positions at a
+// time with SSE2, which every x86_64 processor has. Elsewhere it is 8 at a
+// time, with the bytes of a u64 (SWAR, SIMD within a register).
+
+// The position of the first occurrence of needle in haystack.
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    #[cfg(target_arch = "x86_64")]
+    return find_sse2(haystack, needle);
+    #[cfg(not(target_arch = "x86_64"))]
+    return find_swar(haystack, needle);
+}
+
+// The position of the first occurrence of byte in haystack.
+pub fn find_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    find(haystack, &[byte])
+}
+
+// The position of the last occurrence of byte in haystack.
+pub fn rfind_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    haystack.iter().rposition(|b| *b == byte)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn find_sse2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    use std::arch::x86_64::{
+        __m128i, _mm_and_si128, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    let last = needle.len() - 1;
+    let mut i = 0;
+    // SSE2 is part of x86_64, so the intrinsics are always available, and
+    // the loads stay within haystack as both end before its end.
+    unsafe {
+        let first_bytes = _mm_set1_epi8(needle[0] as i8);
+        let last_bytes = _mm_set1_epi8(needle[last] as i8);
+        while i + last + 16 <= haystack.len() {
+            let starts = _mm_loadu_si128(haystack.as_ptr().add(i) as *const __m128i);
+            let ends = _mm_loadu_si128(haystack.as_ptr().add(i + last) as *const __m128i);
+            let both = _mm_and_si128(
+                _mm_cmpeq_epi8(starts, first_bytes),
+                _mm_cmpeq_epi8(ends, last_bytes),
+            );
+            // One bit for each of the 16 positions.
+            let mut candidates = _mm_movemask_epi8(both) as u32;
+            while candidates != 0 {
+                let at = i + candidates.trailing_zeros() as usize;
+                if &haystack[at..at + needle.len()] == needle {
+                    return Some(at);
+                }
+                candidates &= candidates - 1;
+            }
+            i += 16;
+        }
+    }
+    find_scalar(&haystack[i..], needle).map(|at| i + at)
+}
+
+// Used on x86_64 only by the tests, which check it against the others.
+#[cfg_attr(all(target_arch = "x86_64", not(test)), allow(dead_code))]
+fn find_swar(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    const ONES: u64 = 0x0101_0101_0101_0101;
+    const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+    let last = needle.len() - 1;
+    let first_bytes = ONES * needle[0] as u64;
+    let last_bytes = ONES * needle[last] as u64;
+    let mut i = 0;
+    while i + last + 8 <= haystack.len() {
+        let word = |at: usize| u64::from_le_bytes(haystack[at..at + 8].try_into().unwrap());
+        // A zero byte where both the first and the last byte match.
+        let both = (word(i) ^ first_bytes) | (word(i + last) ^ last_bytes);
+        // Sets the high bit of every zero byte. A borrow may also set it
+        // for some bytes after a zero one, those are ruled out below.
+        let mut candidates = both.wrapping_sub(ONES) & !both & HIGHS;
+        while candidates != 0 {
+            let at = i + candidates.trailing_zeros() as usize / 8;
+            if &haystack[at..at + needle.len()] == needle {
+                return Some(at);
+            }
+            candidates &= candidates - 1;
+        }
+        i += 8;
+    }
+    find_scalar(&haystack[i..], needle).map(|at| i + at)
+}
+
+fn find_scalar(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// The lines of haystack holding needle, each once, as its line number and
+// the range of the line without the "\n" ending it. Lines are only looked
+// for around the occurrences of needle, and the lines in between are just
+// counted.
+pub struct Lines<'a> {
+    haystack: &'a [u8],
+    needle: &'a [u8],
+    // Where to go on searching, always at the start of a line.
+    pos: usize,
+    // The number of lines before pos.
+    lines_before: usize,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(haystack: &'a [u8], needle: &'a [u8]) -> Lines<'a> {
+        Lines {
+            haystack,
+            needle,
+            pos: 0,
+            lines_before: 0,
+        }
+    }
+}
+
+impl Iterator for Lines<'_> {
+    type Item = (usize, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.haystack.len() {
+            return None;
+        }
+        let rest = &self.haystack[self.pos..];
+        let found = self.pos + find(rest, self.needle)?;
+
+        let skipped = &self.haystack[self.pos..found];
+        let start = rfind_byte(skipped, b'\n').map_or(self.pos, |at| self.pos + at + 1);
+        let line_number =
+            self.lines_before + count_byte(&self.haystack[self.pos..start], b'\n') + 1;
+        let end =
+            find_byte(&self.haystack[found..], b'\n').map_or(self.haystack.len(), |at| found + at);
+
+        self.pos = end + 1;
+        self.lines_before = line_number;
+        Some((line_number, start, end))
+    }
+}
+
+// The number of times byte occurs in haystack, for counting the lines
+// skipped over. It reads every byte, so it is vectorized the same way.
+fn count_byte(haystack: &[u8], byte: u8) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    return count_sse2(haystack, byte);
+    #[cfg(not(target_arch = "x86_64"))]
+    return count_swar(haystack, byte);
+}
+
+#[cfg(target_arch = "x86_64")]
+fn count_sse2(haystack: &[u8], byte: u8) -> usize {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    let mut blocks = haystack.chunks_exact(16);
+    let mut count = 0;
+    // As in find_sse2, every load is of a whole block of haystack.
+    unsafe {
+        let bytes = _mm_set1_epi8(byte as i8);
+        for block in &mut blocks {
+            let block = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+            count += _mm_movemask_epi8(_mm_cmpeq_epi8(block, bytes)).count_ones() as usize;
+        }
+    }
+    count + blocks.remainder().iter().filter(|b| **b == byte).count()
+}
+
+#[cfg_attr(all(target_arch = "x86_64", not(test)), allow(dead_code))]
+fn count_swar(haystack: &[u8], byte: u8) -> usize {
+    const LOWS: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+    let bytes = 0x0101_0101_0101_0101 * byte as u64;
+
+    let mut words = haystack.chunks_exact(8);
+    let mut count = 0;
+    for word in &mut words {
+        let other = u64::from_le_bytes(word.try_into().unwrap()) ^ bytes;
+        // Sets the high bit of every byte that isn't zero. Unlike finding a
+        // zero byte, this is exact, so the zero bytes can be counted.
+        let nonzero = (((other & LOWS) + LOWS) | other) & !LOWS;
+        count += 8 - nonzero.count_ones() as usize;
+    }
+    count + words.remainder().iter().filter(|b| **b == byte).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts the newlines of haystack in two parts, so neither is a whole
+    // number of blocks.
+    fn count_in_parts(haystack: &[u8], count: fn(&[u8], u8) -> usize) -> usize {
+        count(&haystack[..3], b'\n') + count(&haystack[3..], b'\n')
+    }
+
+    #[test]
+    fn finds_like_scalar() {
+        // Long enough for the vector loops, with the needles at every offset
+        // around their ends.
+        let haystack: Vec<u8> = (0..300u32)
+            .map(|i| b"abcab\nxyz"[(i * 7 % 9) as usize])
+            .collect();
+        let needles: [&[u8]; 6] = [b"a", b"\n", b"ab\n", b"zab", b"cabc", b"q"];
+        for start in 0..40 {
+            let haystack = &haystack[start..];
+            for needle in needles {
+                let expected = find_scalar(haystack, needle);
+                assert_eq!(expected, find(haystack, needle));
+                assert_eq!(expected, find_swar(haystack, needle));
+            }
+        }
+        let newlines = haystack.iter().filter(|b| **b == b'\n').count();
+        assert_eq!(newlines, count_in_parts(&haystack, count_byte));
+        assert_eq!(newlines, count_in_parts(&haystack, count_swar));
+        assert_eq!(None, find(b"short", b"longer than it"));
+        assert_eq!(Some(0), find(b"x", b""));
+    }
+
+    #[test]
+    fn matching_lines() {
+        let haystack = b"one fast\ntwo\nthree fast fast\r\n\nfast";
+        let lines: Vec<_> = Lines::new(haystack, b"fast").collect();
+        assert_eq!(vec![(1, 0, 8), (3, 13, 29), (5, 31, 35)], lines);
+        assert_eq!(0, Lines::new(haystack, b"slow").count());
+    }
+}
@@ -1,8 +1,10 @@
 use std::env;
+use std::io::{self, Write};
 use std::process;
 
 // Pull struct into scope
-use minigrep::Config;
+use minigrep::args::ArgsError;
+use minigrep::{Config, Error};
 
 fn main() {
     // https://doc.rust-lang.org/std/env/index.html
@@ -10,18 +12,31 @@ fn main() {
 
     // unwrap_or_else takes closure for Err variant.
     let config: Config = Config::build(env::args()).unwrap_or_else(|err| {
+        // Asking for the help or version is not a problem, so print it and succeed.
+        if let Error::Args(ArgsError::Help | ArgsError::Version) = err {
+            // Ignore a closed stdout, help piped to head is still a success.
+            let _ = writeln!(io::stdout(), "{err}");
+            process::exit(0);
+        }
+
         eprintln!("Problem passing arguments: {err}");
         // process::exit stops the program immediately like panic but does not print additional info like panic does.
-        process::exit(1);
+        // Like grep, exit status 2 means an error, 1 that nothing matched.
+        process::exit(2);
     });
 
-    // Use of if-let to capture the error variant of the Result enum. We do not care about the okay variant
-    // since it returns () and just specifies that everything works.
-    // if-let is in this case similar to unwrap_or_else, we're just discarding the okay case.
-
-    // Call run function from library
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    // Call run function from library. The okay variant tells whether anything matched.
+    match minigrep::run(config) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        // The inputs that couldn't be searched were already reported.
+        Err(Error::Inputs(_)) => process::exit(2),
+        // The reader went away, like head does after enough lines, so there
+        // is no one left to tell.
+        Err(Error::Output(err)) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            process::exit(2);
+        }
     }
 }
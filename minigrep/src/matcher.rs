@@ -0,0 +1,181 @@
+// Finding the matches within a line. Every kind of search minigrep does is a
+// Matcher, and programs using minigrep as a library can pass their own to a
+// Searcher. A matcher returns the byte ranges of the matches in a line from
+// left to right, without overlaps.
+
+use crate::aho_corasick::AhoCorasick;
+use crate::casefold;
+use crate::regex::Regex;
+
+pub trait Matcher {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)>;
+
+    fn is_match(&self, line: &str) -> bool {
+        !self.find_iter(line).is_empty()
+    }
+
+    // A string every match contains, if there is one. Searching a whole
+    // input at once only runs the matcher on the lines holding it.
+    fn literal(&self) -> Option<&str> {
+        None
+    }
+
+    // How far a match is from the query, for matchers that find more than
+    // exact matches.
+    fn distance(&self, _found: &str) -> Option<usize> {
+        None
+    }
+}
+
+// So a borrowed matcher, or a trait object, can be passed where a matcher is
+// taken by value.
+impl<M: Matcher + ?Sized> Matcher for &M {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        (**self).find_iter(line)
+    }
+
+    fn literal(&self) -> Option<&str> {
+        (**self).literal()
+    }
+
+    fn distance(&self, found: &str) -> Option<usize> {
+        (**self).distance(found)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        (**self).find_iter(line)
+    }
+
+    fn literal(&self) -> Option<&str> {
+        (**self).literal()
+    }
+
+    fn distance(&self, found: &str) -> Option<usize> {
+        (**self).distance(found)
+    }
+}
+
+// A fixed string, compared byte for byte.
+#[derive(Debug, Clone)]
+pub struct Substring {
+    query: String,
+}
+
+impl Substring {
+    pub fn new(query: &str) -> Substring {
+        Substring {
+            query: query.to_string(),
+        }
+    }
+}
+
+impl Matcher for Substring {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        line.match_indices(&self.query)
+            .map(|(start, found)| (start, start + found.len()))
+            .collect()
+    }
+
+    fn literal(&self) -> Option<&str> {
+        Some(self.query.as_str()).filter(|query| !query.is_empty())
+    }
+}
+
+// A fixed string, compared with Unicode simple case folding.
+#[derive(Debug, Clone)]
+pub struct CaseInsensitive {
+    // The query is folded once, up front.
+    folded: Vec<char>,
+}
+
+impl CaseInsensitive {
+    pub fn new(query: &str) -> CaseInsensitive {
+        CaseInsensitive {
+            folded: query.chars().map(casefold::fold).collect(),
+        }
+    }
+}
+
+impl Matcher for CaseInsensitive {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        casefold::find_iter(&self.folded, line)
+    }
+}
+
+// Several fixed strings at once. Where they overlap, the leftmost and then
+// longest match wins.
+#[derive(Debug, Clone)]
+pub struct MultiPattern {
+    automaton: AhoCorasick,
+    ignore_case: bool,
+}
+
+impl MultiPattern {
+    pub fn new<P: AsRef<str>>(patterns: &[P], ignore_case: bool) -> MultiPattern {
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|pattern| match ignore_case {
+                true => pattern.as_ref().chars().map(casefold::fold).collect(),
+                false => pattern.as_ref().to_string(),
+            })
+            .collect();
+        MultiPattern {
+            automaton: AhoCorasick::new(&patterns),
+            ignore_case,
+        }
+    }
+}
+
+impl Matcher for MultiPattern {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        match self.ignore_case {
+            true => self.automaton.find_iter_folded(line),
+            false => self.automaton.find_iter(line.as_bytes()),
+        }
+    }
+}
+
+// Matches the patterns the automaton was built from, as they are.
+impl Matcher for AhoCorasick {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        AhoCorasick::find_iter(self, line.as_bytes())
+    }
+}
+
+// Case sensitivity is decided when the regex is compiled.
+impl Matcher for Regex {
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        Regex::find_iter(self, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matchers() {
+        let line = "Rust and rust, trust";
+        assert_eq!(
+            vec![(9, 13), (16, 20)],
+            Substring::new("rust").find_iter(line)
+        );
+        assert_eq!(
+            vec![(0, 4), (9, 13), (16, 20)],
+            CaseInsensitive::new("RUST").find_iter(line)
+        );
+        assert_eq!(
+            vec![(0, 4), (5, 8), (9, 14)],
+            MultiPattern::new(&["and", "trust", "rust"], true).find_iter("RUST AND TRUST")
+        );
+
+        let regex = Regex::build(r"\btr?ust", false).unwrap();
+        let matchers: Vec<Box<dyn Matcher>> = vec![Box::new(regex), Box::new(Substring::new("&"))];
+        assert!(matchers[0].is_match(line));
+        assert!(!matchers[1].is_match(line));
+        assert_eq!(Some("&"), matchers[1].literal());
+        assert_eq!(None, matchers[0].literal());
+    }
+}
@@ -0,0 +1,143 @@
+// Memory mapping files, so a large file can be searched as one slice without
+// copying it into memory first. minigrep has no dependencies, so on Unix the
+// file is mapped by calling mmap(2) directly. Elsewhere, and for empty files
+// which can't be mapped, the file is read instead.
+//
+// A mapped file changes along with the file. When another process truncates
+// the file while it is searched, reading the pages that are gone kills the
+// process with SIGBUS, a risk every grep that maps files takes.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+
+pub struct Mmap {
+    inner: Inner,
+}
+
+enum Inner {
+    #[cfg(unix)]
+    Mapped {
+        ptr: *mut sys::c_void,
+        len: usize,
+    },
+    Read(Vec<u8>),
+}
+
+impl Mmap {
+    pub fn map(file: &File) -> io::Result<Mmap> {
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Ok(Mmap {
+                inner: Inner::Read(Vec::new()),
+            });
+        }
+        let len = usize::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::OutOfMemory, "file too large to map"))?;
+        Self::map_len(file, len)
+    }
+
+    #[cfg(unix)]
+    fn map_len(file: &File, len: usize) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+
+        // The mapping is private and read only, and it stays valid after
+        // the file is closed.
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            inner: Inner::Mapped { ptr, len },
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn map_len(mut file: &File, len: usize) -> io::Result<Mmap> {
+        use std::io::Read;
+
+        let mut bytes = Vec::with_capacity(len);
+        file.read_to_end(&mut bytes)?;
+        Ok(Mmap {
+            inner: Inner::Read(bytes),
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.inner {
+            // The mapping lives as long as self and is len bytes long.
+            #[cfg(unix)]
+            Inner::Mapped { ptr, len } => unsafe {
+                std::slice::from_raw_parts(*ptr as *const u8, *len)
+            },
+            Inner::Read(bytes) => bytes,
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Inner::Mapped { ptr, len } = self.inner {
+            unsafe {
+                sys::munmap(ptr, len);
+            }
+        }
+    }
+}
+
+// The parts of the C library used, with the values Linux and the BSDs,
+// macOS among them, agree on.
+#[cfg(unix)]
+mod sys {
+    pub use std::ffi::c_void;
+    use std::os::raw::{c_int, c_long};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn maps_contents() {
+        let path = std::env::temp_dir().join(format!("minigrep-mmap-{}", std::process::id()));
+        fs::write(&path, "mapped\ncontents\n").unwrap();
+        let map = Mmap::map(&File::open(&path).unwrap()).unwrap();
+        assert_eq!(b"mapped\ncontents\n", &map[..]);
+
+        fs::write(&path, "").unwrap();
+        let map = Mmap::map(&File::open(&path).unwrap()).unwrap();
+        assert!(map.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
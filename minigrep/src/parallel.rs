@@ -0,0 +1,418 @@
+// Searches many files at once. One thread walks the paths and hands them out
+// to a pool of workers. Every worker prints the results of a file into a
+// buffer of its own, and the buffers are written to stdout whole, so the
+// lines of one file are never mixed with those of another.
+//
+// A file with more output than BUFFER_LIMIT isn't held in memory however
+// large it is. Its worker waits for its turn, which with --sort=path is when
+// the files before it are written, and then writes the rest of the output
+// straight to stdout, holding on to it until the file is done.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use crate::printer::{self, Printer, Stats};
+use crate::{Config, Error, Finder, OutputMode};
+
+// The most output of one file kept in memory before it is written out.
+const BUFFER_LIMIT: usize = 1024 * 1024;
+
+// The results of one file: the output still to write, whether it counts as
+// a success for the exit status and the stats for --json. The output is
+// empty when the worker wrote it itself.
+type Output = Result<(Vec<u8>, bool, Stats), Error>;
+
+// Stdout, shared by the thread writing the buffers and the workers writing
+// large outputs themselves.
+struct Shared<W: Write> {
+    state: Mutex<State<W>>,
+    // Signalled when the next file's turn comes or the output is closed.
+    turn: Condvar,
+}
+
+struct State<W: Write> {
+    out: W,
+    // Groups of context lines from different files are separated by "--",
+    // the same as groups within one file.
+    separate: bool,
+    printed: bool,
+    // The index of the file to write next, with --sort=path.
+    next: usize,
+    // Nothing more is written, after a failure or in quiet mode.
+    closed: bool,
+}
+
+impl<W: Write> Shared<W> {
+    fn new(out: W, config: &Config) -> Shared<W> {
+        let separate = !config.print.vimgrep
+            && !config.print.json
+            && (config.print.before_context > 0 || config.print.after_context > 0);
+        Shared {
+            state: Mutex::new(State {
+                out,
+                separate,
+                printed: false,
+                next: 0,
+                closed: false,
+            }),
+            turn: Condvar::new(),
+        }
+    }
+
+    // Stops the workers still waiting for their turn.
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.turn.notify_all();
+    }
+}
+
+impl<W: Write> State<W> {
+    // Writes the start of a file's output, after a separator if needed.
+    fn begin(&mut self, buffer: &[u8]) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if self.separate && self.printed {
+            writeln!(self.out, "--")?;
+        }
+        self.printed = true;
+        self.out.write_all(buffer)
+    }
+}
+
+// Where a worker prints the results of one file: a buffer, until there is
+// too much output and it goes to stdout instead.
+struct FileOutput<'a, W: Write> {
+    shared: &'a Shared<W>,
+    index: usize,
+    sort_by_path: bool,
+    buffer: Vec<u8>,
+    // Held while the output goes straight to stdout.
+    stdout: Option<MutexGuard<'a, State<W>>>,
+    // Whether writing to stdout failed, which ends the search, unlike an
+    // error reading the file.
+    failed: bool,
+}
+
+impl<'a, W: Write> FileOutput<'a, W> {
+    fn new(shared: &'a Shared<W>, index: usize, sort_by_path: bool) -> FileOutput<'a, W> {
+        FileOutput {
+            shared,
+            index,
+            sort_by_path,
+            buffer: Vec::new(),
+            stdout: None,
+            failed: false,
+        }
+    }
+
+    // Waits for the file's turn and writes what was buffered.
+    fn stream(&mut self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        while self.sort_by_path && state.next != self.index && !state.closed {
+            state = self.shared.turn.wait(state).unwrap();
+        }
+        // Not ErrorKind::Interrupted, write_all would keep on trying.
+        if state.closed {
+            return Err(io::Error::other("search stopped"));
+        }
+        let written = state.begin(&self.buffer);
+        self.failed |= written.is_err();
+        self.buffer = Vec::new();
+        self.stdout = Some(state);
+        written
+    }
+}
+
+impl<W: Write> Write for FileOutput<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(state) = &mut self.stdout {
+            let written = state.out.write(buf);
+            self.failed |= written.is_err();
+            return written;
+        }
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() > BUFFER_LIMIT {
+            self.stream()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stdout {
+            Some(state) => state.out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn run(
+    config: &Config,
+    finder: &Finder,
+    threads: usize,
+    with_path: bool,
+    start: Instant,
+) -> Result<bool, Error> {
+    // Set to stop searching early, after failing to write the results or the
+    // first match in quiet mode.
+    let stop = AtomicBool::new(false);
+    let (path_tx, path_rx) = mpsc::sync_channel::<(usize, PathBuf)>(threads * 4);
+    // Bounded as well, so the workers wait while a large output is written
+    // instead of piling up theirs.
+    let (output_tx, output_rx) = mpsc::sync_channel::<(usize, Output)>(threads * 4);
+    let path_rx = Mutex::new(path_rx);
+    let shared = Shared::new(io::stdout(), config);
+
+    thread::scope(|scope| {
+        let stop = &stop;
+        let path_rx = &path_rx;
+        let shared = &shared;
+
+        let errors_tx = output_tx.clone();
+        scope.spawn(move || {
+            for (index, input) in crate::inputs(config).enumerate() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match input {
+                    Ok(path) => {
+                        if path_tx.send((index, path)).is_err() {
+                            break;
+                        }
+                    }
+                    // Walk errors go straight to the output, in their place.
+                    Err(err) => {
+                        if errors_tx.send((index, Err(err))).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let output_tx = output_tx.clone();
+            scope.spawn(move || loop {
+                // The lock is only held while waiting for the next path.
+                let next = path_rx.lock().unwrap().recv();
+                let (index, path) = match next {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                if stop.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let out = FileOutput::new(shared, index, config.sort_by_path);
+                let mut printer = Printer::new(out, &config.print, with_path);
+                let searched = crate::search_path(config, finder, &path, &mut printer);
+                let stats = printer.stats();
+                // Stdout is let go before sending, the thread receiving the
+                // output may be waiting for it.
+                let FileOutput { buffer, failed, .. } = printer.into_inner();
+                let output = match searched {
+                    Ok(matched) => Ok((buffer, matched, stats)),
+                    Err(err) if failed => Err(Error::Output(err)),
+                    Err(err) => Err(crate::input_error(&path, err)),
+                };
+                // Once the output is no longer read, the remaining paths are
+                // still drained so the walking thread can't block on them.
+                let _ = output_tx.send((index, output));
+            });
+        }
+        // Only the other threads hold senders now, so the loop below ends with them.
+        drop(output_tx);
+
+        // Writing only ends early when it fails or in quiet mode, when the
+        // remaining files no longer need to be searched.
+        let result = write_outputs(config, output_rx, shared);
+        stop.store(true, Ordering::Relaxed);
+        shared.close();
+
+        let (matched, stats, errors) = result?;
+        if matched && config.mode == OutputMode::Quiet {
+            return Ok(true);
+        }
+        if config.print.json {
+            let stdout = io::stdout().lock();
+            printer::print_summary(stdout, &stats, start.elapsed()).map_err(Error::Output)?;
+        }
+        match errors {
+            0 => Ok(matched),
+            errors => Err(Error::Inputs(errors)),
+        }
+    })
+}
+
+// Writes the outputs as they arrive, or in the order of the paths with
+// --sort=path, and reports the files that couldn't be searched. Returns
+// whether any file matched, the stats of all of them and the number of
+// errors.
+fn write_outputs<W: Write>(
+    config: &Config,
+    outputs: mpsc::Receiver<(usize, Output)>,
+    shared: &Shared<W>,
+) -> Result<(bool, Stats, usize), Error> {
+    let mut matched = false;
+    let mut stats = Stats::default();
+    let mut errors = 0;
+
+    // Outputs that arrived before those of earlier paths, with --sort=path.
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for (index, output) in outputs {
+        let ready = if config.sort_by_path {
+            pending.insert(index, output);
+            let mut ready = Vec::new();
+            while let Some(output) = pending.remove(&next) {
+                ready.push(output);
+                next += 1;
+            }
+            ready
+        } else {
+            vec![output]
+        };
+
+        for output in ready {
+            let (buffer, file_matched, file_stats) = match output {
+                Ok(output) => output,
+                Err(err @ Error::Output(_)) => return Err(err),
+                Err(err) => {
+                    crate::print_error(&err);
+                    errors += 1;
+                    continue;
+                }
+            };
+            matched |= file_matched;
+            stats.add(&file_stats);
+            if matched && config.mode == OutputMode::Quiet {
+                return Ok((true, stats, errors));
+            }
+            let mut state = shared.state.lock().unwrap();
+            state.begin(&buffer).map_err(Error::Output)?;
+        }
+
+        // The file after those written may now write its own output.
+        if config.sort_by_path {
+            shared.state.lock().unwrap().next = next;
+            shared.turn.notify_all();
+        }
+    }
+
+    Ok((matched, stats, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        let args = ["minigrep", "x"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string());
+        Config::build(args).unwrap()
+    }
+
+    fn write(args: &[&str], outputs: Vec<(usize, Output)>) -> (bool, String) {
+        let config = config(args);
+        let (tx, rx) = mpsc::channel();
+        for output in outputs {
+            tx.send(output).unwrap();
+        }
+        drop(tx);
+
+        let shared = Shared::new(Vec::new(), &config);
+        let (matched, _, _) = write_outputs(&config, rx, &shared).unwrap();
+        let out = shared.state.into_inner().unwrap().out;
+        (matched, String::from_utf8(out).unwrap())
+    }
+
+    fn output(text: &str, matched: bool) -> Output {
+        Ok((text.as_bytes().to_vec(), matched, Stats::default()))
+    }
+
+    #[test]
+    fn outputs_stay_whole() {
+        let outputs = vec![
+            (2, output("c:1\nc:2\n", true)),
+            (0, output("", false)),
+            (1, output("b:1\n", true)),
+        ];
+        assert_eq!((true, String::from("c:1\nc:2\nb:1\n")), write(&[], outputs));
+    }
+
+    #[test]
+    fn errors_are_skipped() {
+        let missing = Error::NotFound(PathBuf::from("missing.txt"));
+        let outputs = vec![(0, Err(missing)), (1, output("b:1\n", true))];
+        assert_eq!((true, String::from("b:1\n")), write(&[], outputs));
+    }
+
+    #[test]
+    fn sort_by_path() {
+        let outputs = vec![
+            (2, output("c:1\n", true)),
+            (1, output("b:1\n", true)),
+            (0, output("", false)),
+        ];
+        assert_eq!(
+            (true, String::from("b:1\nc:1\n")),
+            write(&["--sort=path"], outputs)
+        );
+
+        // Files with context are separated like groups within a file.
+        let outputs = vec![(1, output("b-1\nb:2\n", true)), (0, output("a:1\n", true))];
+        assert_eq!(
+            (true, String::from("a:1\n--\nb-1\nb:2\n")),
+            write(&["--sort", "path", "-B1"], outputs)
+        );
+    }
+
+    #[test]
+    fn large_outputs_wait_their_turn() {
+        let config = config(&["--sort=path"]);
+        let shared = Shared::new(Vec::new(), &config);
+        let (tx, rx) = mpsc::channel();
+        let line = "b:".repeat(1000) + "\n";
+        let lines = BUFFER_LIMIT / line.len() + 10;
+
+        thread::scope(|scope| {
+            // The second file has too much output to buffer, so it waits
+            // for the first to be written before writing its own.
+            let worker_tx = tx.clone();
+            let (shared, line) = (&shared, &line);
+            scope.spawn(move || {
+                let mut out = FileOutput::new(shared, 1, true);
+                for _ in 0..lines {
+                    out.write_all(line.as_bytes()).unwrap();
+                }
+                assert!(out.buffer.is_empty());
+                drop(out);
+                worker_tx.send((1, output("", true))).unwrap();
+            });
+            tx.send((0, output("a:1\n", true))).unwrap();
+            drop(tx);
+            write_outputs(&config, rx, shared).unwrap();
+        });
+
+        let out = String::from_utf8(shared.state.into_inner().unwrap().out).unwrap();
+        assert_eq!(format!("a:1\n{}", line.repeat(lines)), out);
+    }
+
+    #[test]
+    fn closed_output_stops_large_writes() {
+        let config = config(&[]);
+        let shared = Shared::new(Vec::new(), &config);
+        shared.close();
+        let mut out = FileOutput::new(&shared, 0, false);
+        assert!(out.write_all(&vec![b'a'; BUFFER_LIMIT + 1]).is_err());
+        assert!(!out.failed);
+    }
+}
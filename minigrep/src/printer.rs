@@ -0,0 +1,588 @@
+// Formats search results. Every line of output starts with a prefix of
+// fields separated by ':', the path, line number, column and byte offset,
+// depending on the options, and with --fuzzy "d=" and the edit distance of
+// every match, followed by the line itself. Context lines
+// around the matches use '-' as separator instead, and groups of lines that
+// aren't next to each other are separated by a "--" line, like GNU grep.
+//
+// With --json every event is printed as a JSON object on a line of its own
+// instead, for tools to read:
+//  {"type":"begin","data":{"path":"poem.txt"}}
+//  {"type":"match","data":{"path":"poem.txt","line_number":2,"byte_offset":6,
+//    "line":"safe, fast","submatches":[{"match":"safe","start":0,"end":4}]}}
+//  {"type":"context","data":{...the same as match, without submatches}}
+// With --fuzzy, every submatch also has its "distance".
+//  {"type":"end","data":{"path":"poem.txt","stats":{"matched_lines":1,"matches":1}}}
+//  {"type":"summary","data":{"elapsed_secs":0.001,"stats":{"searches":1,...}}}
+// Files are only begun and ended when something was printed for them.
+
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::color::{self, ColorScheme};
+use crate::sink::Sink;
+use crate::Match;
+
+// The parts of the prefix that can be coloured.
+enum Field {
+    Path,
+    LineNumber,
+    ByteOffset,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    pub line_number: bool,
+    // The 1-based byte column of the first match in the line.
+    pub column: bool,
+    // The 0-based byte offset of the line within the file.
+    pub byte_offset: bool,
+    // One line per match as path:line:column:text, the format vim's
+    // grepformat and most editors expect.
+    pub vimgrep: bool,
+    // The number of lines to print after and before every match.
+    pub after_context: usize,
+    pub before_context: usize,
+    // The colours to use, None when the output is not coloured.
+    pub colors: Option<ColorScheme>,
+    // Print JSON Lines events instead of plain lines.
+    pub json: bool,
+}
+
+// Counts of what was printed, for the end and summary JSON events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // The number of inputs searched, and those of them with a match.
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub matched_lines: usize,
+    // The number of matches within the lines.
+    pub matches: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+pub struct Printer<W: Write> {
+    out: W,
+    options: PrintOptions,
+    with_path: bool,
+    // Whether a group of context lines was printed, so the next one needs a separator.
+    printed_group: bool,
+    // The line number of the last line printed from the current input.
+    last_printed: Option<usize>,
+    // Whether the begin event of the current input was printed, with --json.
+    begun: bool,
+    // The stats of the current input, and of all the ones before it.
+    file_stats: Stats,
+    stats: Stats,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, options: &PrintOptions, with_path: bool) -> Printer<W> {
+        Printer {
+            out,
+            options: options.clone(),
+            with_path,
+            printed_group: false,
+            last_printed: None,
+            begun: false,
+            file_stats: Stats::default(),
+            stats: Stats::default(),
+        }
+    }
+
+    // Resets the state of the previous input before the lines of a new one.
+    pub fn begin_file(&mut self) {
+        self.last_printed = None;
+        self.begun = false;
+        self.file_stats = Stats::default();
+    }
+
+    // Adds up the stats of the input, printing its end event with --json.
+    pub fn end_file(&mut self, path: &Path) -> io::Result<()> {
+        self.file_stats.searches = 1;
+        self.file_stats.searches_with_match = (self.file_stats.matched_lines > 0) as usize;
+        self.stats.add(&self.file_stats);
+
+        if !self.begun {
+            return Ok(());
+        }
+        self.begun = false;
+        let stats = self.file_stats;
+        writeln!(
+            self.out,
+            "{{\"type\":\"end\",\"data\":{{\"path\":{},\"stats\":\
+{{\"matched_lines\":{},\"matches\":{}}}}}}}",
+            json_string(&path.to_string_lossy()),
+            stats.matched_lines,
+            stats.matches
+        )
+    }
+
+    // The stats of every input ended so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Prints the "--" separator before a line that doesn't follow the last
+    // one printed, when there is context around the matches.
+    fn separate(&mut self, line_number: usize) -> io::Result<()> {
+        let context = !self.options.vimgrep
+            && (self.options.before_context > 0 || self.options.after_context > 0);
+        let contiguous = self
+            .last_printed
+            .is_some_and(|last| line_number <= last + 1);
+        if context && self.printed_group && !contiguous && !self.options.json {
+            writeln!(self.out, "--")?;
+        }
+        self.printed_group = true;
+        self.last_printed = Some(line_number);
+        Ok(())
+    }
+
+    // Prints the number of matching lines in an input, for --count.
+    pub fn print_count(&mut self, path: &Path, count: usize) -> io::Result<()> {
+        if self.with_path {
+            let path = self.path_field(path);
+            self.write_prefix(&[path], ":")?;
+        }
+        writeln!(self.out, "{count}")
+    }
+
+    // Prints just the path of an input, for --files-with-matches and
+    // --files-without-match.
+    pub fn print_path(&mut self, path: &Path) -> io::Result<()> {
+        let path = self.path_field(path);
+        writeln!(self.out, "{path}")
+    }
+
+    // Printed instead of the matching lines of a binary input.
+    pub fn print_binary(&mut self, path: &Path) -> io::Result<()> {
+        self.file_stats.matched_lines += 1;
+        // There is no JSON event for it, only the stats count the match.
+        if self.options.json {
+            return Ok(());
+        }
+        writeln!(self.out, "Binary file {} matches", path.display())
+    }
+
+    // Like print_binary, for a binary file --replace didn't rewrite.
+    pub fn print_binary_unchanged(&mut self, path: &Path) -> io::Result<()> {
+        self.file_stats.matched_lines += 1;
+        if self.options.json {
+            return Ok(());
+        }
+        writeln!(
+            self.out,
+            "Binary file {} matches, left unchanged",
+            path.display()
+        )
+    }
+
+    // Prints the changes --replace makes to a file, for --diff.
+    pub fn print_diff(&mut self, diff: &str) -> io::Result<()> {
+        self.out.write_all(diff.as_bytes())
+    }
+
+    fn print_context(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+    ) -> io::Result<()> {
+        if self.options.json {
+            let context = Match {
+                line_number,
+                byte_offset,
+                line,
+                spans: Vec::new(),
+                distances: Vec::new(),
+            };
+            return self.print_json("context", path, &context);
+        }
+
+        let mut prefix = Vec::new();
+        if self.with_path {
+            prefix.push(self.path_field(path));
+        }
+        if self.options.line_number {
+            prefix.push(self.field(Field::LineNumber, line_number));
+        }
+        if self.options.byte_offset {
+            prefix.push(self.field(Field::ByteOffset, byte_offset));
+        }
+        self.write_prefix(&prefix, "-")?;
+        writeln!(self.out, "{line}")
+    }
+
+    pub fn print_match(&mut self, path: &Path, found: &Match) -> io::Result<()> {
+        if self.options.json {
+            return self.print_json("match", path, found);
+        }
+        if self.options.vimgrep {
+            // Lines selected by --invert-match have no spans, but are printed once.
+            let first = [(0, 0)];
+            let spans = if found.spans.is_empty() {
+                &first[..]
+            } else {
+                &found.spans
+            };
+            for (i, (start, end)) in spans.iter().enumerate() {
+                let mut prefix = vec![
+                    self.path_field(path),
+                    self.field(Field::LineNumber, found.line_number),
+                    self.field(Field::LineNumber, start + 1),
+                ];
+                if let Some(distance) = found.distances.get(i) {
+                    prefix.push(self.field(Field::LineNumber, format!("d={distance}")));
+                }
+                self.write_prefix(&prefix, ":")?;
+                self.write_line(found.line, &[(*start, *end)])?;
+            }
+            return Ok(());
+        }
+
+        let mut prefix = Vec::new();
+        if self.with_path {
+            prefix.push(self.path_field(path));
+        }
+        if self.options.line_number {
+            prefix.push(self.field(Field::LineNumber, found.line_number));
+        }
+        if self.options.column {
+            let column = found.spans.first().map_or(0, |(start, _)| *start) + 1;
+            prefix.push(self.field(Field::LineNumber, column));
+        }
+        if self.options.byte_offset {
+            prefix.push(self.field(Field::ByteOffset, found.byte_offset));
+        }
+        // The distance of every match with --fuzzy, separated by commas and
+        // labelled so it can't be taken for a column or an offset.
+        if !found.distances.is_empty() {
+            let distances: Vec<String> = found.distances.iter().map(usize::to_string).collect();
+            let distances = format!("d={}", distances.join(","));
+            prefix.push(self.field(Field::LineNumber, distances));
+        }
+        self.write_prefix(&prefix, ":")?;
+        self.write_line(found.line, &found.spans)
+    }
+
+    // Prints a match or context event, after the begin event of the input
+    // if this is the first one.
+    fn print_json(&mut self, kind: &str, path: &Path, line: &Match) -> io::Result<()> {
+        let path = json_string(&path.to_string_lossy());
+        if !self.begun {
+            self.begun = true;
+            writeln!(
+                self.out,
+                "{{\"type\":\"begin\",\"data\":{{\"path\":{path}}}}}"
+            )?;
+        }
+
+        let mut data = format!(
+            "\"path\":{path},\"line_number\":{},\"byte_offset\":{},\"line\":{}",
+            line.line_number,
+            line.byte_offset,
+            json_string(line.line)
+        );
+        if kind == "match" {
+            let submatches: Vec<String> = line
+                .spans
+                .iter()
+                .enumerate()
+                .map(|(i, (start, end))| {
+                    let text = json_string(&line.line[*start..*end]);
+                    let distance = match line.distances.get(i) {
+                        Some(distance) => format!(",\"distance\":{distance}"),
+                        None => String::new(),
+                    };
+                    format!("{{\"match\":{text},\"start\":{start},\"end\":{end}{distance}}}")
+                })
+                .collect();
+            data.push_str(&format!(",\"submatches\":[{}]", submatches.join(",")));
+        }
+        writeln!(self.out, "{{\"type\":\"{kind}\",\"data\":{{{data}}}}}")
+    }
+
+    fn path_field(&self, path: &Path) -> String {
+        self.field(Field::Path, path.display())
+    }
+
+    fn field(&self, field: Field, value: impl Display) -> String {
+        let value = value.to_string();
+        match &self.options.colors {
+            Some(colors) => {
+                let code = match field {
+                    Field::Path => &colors.path,
+                    Field::LineNumber => &colors.line_number,
+                    Field::ByteOffset => &colors.byte_offset,
+                };
+                color::paint(code, &value)
+            }
+            None => value,
+        }
+    }
+
+    // Writes the fields, each followed by the separator.
+    fn write_prefix(&mut self, prefix: &[String], separator: &str) -> io::Result<()> {
+        let separator = match &self.options.colors {
+            Some(colors) => color::paint(&colors.separator, separator),
+            None => separator.to_string(),
+        };
+        for field in prefix {
+            write!(self.out, "{field}{separator}")?;
+        }
+        Ok(())
+    }
+
+    // Writes the line with the spans highlighted when colours are enabled.
+    fn write_line(&mut self, line: &str, spans: &[(usize, usize)]) -> io::Result<()> {
+        let colors = match &self.options.colors {
+            Some(colors) => colors,
+            None => return writeln!(self.out, "{line}"),
+        };
+
+        let mut written = 0;
+        for (start, end) in spans {
+            // Empty matches have nothing to highlight.
+            if start == end {
+                continue;
+            }
+            write!(self.out, "{}", &line[written..*start])?;
+            write!(
+                self.out,
+                "{}",
+                color::paint(&colors.matched, &line[*start..*end])
+            )?;
+            written = *end;
+        }
+        writeln!(self.out, "{}", &line[written..])
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+// The lines are printed as the searcher selects them, which has to use the
+// same context as the printer.
+impl<W: Write> Sink for Printer<W> {
+    fn matched(&mut self, path: &Path, found: &Match) -> io::Result<bool> {
+        self.file_stats.matched_lines += 1;
+        self.file_stats.matches += found.spans.len();
+        self.separate(found.line_number)?;
+        self.print_match(path, found)?;
+        Ok(true)
+    }
+
+    fn context(&mut self, path: &Path, line: &Match) -> io::Result<()> {
+        self.separate(line.line_number)?;
+        self.print_context(path, line.line_number, line.byte_offset, line.line)
+    }
+}
+
+// Prints the summary event closing the --json output.
+pub fn print_summary(mut out: impl Write, stats: &Stats, elapsed: Duration) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"type\":\"summary\",\"data\":{{\"elapsed_secs\":{:.6},\"stats\":{{\
+\"searches\":{},\"searches_with_match\":{},\"matched_lines\":{},\"matches\":{}}}}}}}",
+        elapsed.as_secs_f64(),
+        stats.searches,
+        stats.searches_with_match,
+        stats.matched_lines,
+        stats.matches
+    )
+}
+
+// Quotes text as a JSON string, escaping what JSON requires.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Substring;
+    use crate::sink::Searcher;
+
+    fn print_lines(options: PrintOptions, contents: &str, query: &str) -> String {
+        let mut printer = Printer::new(Vec::new(), &options, false);
+        let searcher = Searcher {
+            before_context: options.before_context,
+            after_context: options.after_context,
+            ..Default::default()
+        };
+        let path = Path::new("poem.txt");
+        let matcher = Substring::new(query);
+        searcher
+            .search_reader(&matcher, contents.as_bytes(), path, &mut printer)
+            .unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    fn print(options: PrintOptions, with_path: bool) -> String {
+        let found = Match {
+            line_number: 2,
+            byte_offset: 6,
+            line: "safe, fast, safe",
+            spans: vec![(0, 4), (12, 16)],
+            distances: Vec::new(),
+        };
+        let mut printer = Printer::new(Vec::new(), &options, with_path);
+        printer.print_match(Path::new("poem.txt"), &found).unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!("safe, fast, safe\n", print(PrintOptions::default(), false));
+
+        let options = PrintOptions {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..Default::default()
+        };
+        assert_eq!("poem.txt:2:1:6:safe, fast, safe\n", print(options, true));
+    }
+
+    #[test]
+    fn context() {
+        let contents = "1\n2 x\n3\n4\n5 x\n6\n7\n8\n9\n10 x\n11";
+        let options = PrintOptions {
+            line_number: true,
+            before_context: 1,
+            after_context: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            "1-1\n2:2 x\n3-3\n4-4\n5:5 x\n6-6\n7-7\n--\n9-9\n10:10 x\n11-11\n",
+            print_lines(options, contents, "x")
+        );
+
+        // Windows that touch are merged without a separator.
+        let options = PrintOptions {
+            after_context: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            "2 x\n3\n4\n5 x\n6\n7\n",
+            print_lines(options, "1\n2 x\n3\n4\n5 x\n6\n7", "x")
+        );
+
+        // Only before_context lines are kept while waiting for a match.
+        let options = PrintOptions {
+            before_context: 1,
+            ..Default::default()
+        };
+        assert_eq!("2\n3 x\n", print_lines(options, "1\n2\n3 x\n4", "x"));
+    }
+
+    #[test]
+    fn colors() {
+        let options = PrintOptions {
+            line_number: true,
+            colors: Some(ColorScheme::default()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "\x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m\
+\x1b[1;31msafe\x1b[0m, fast, \x1b[1;31msafe\x1b[0m\n",
+            print(options, true)
+        );
+    }
+
+    #[test]
+    fn count_and_path() {
+        let mut printer = Printer::new(Vec::new(), &PrintOptions::default(), true);
+        printer.print_count(Path::new("poem.txt"), 3).unwrap();
+        printer.print_path(Path::new("poem.txt")).unwrap();
+        assert_eq!(
+            "poem.txt:3\npoem.txt\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn vimgrep() {
+        let options = PrintOptions {
+            vimgrep: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "poem.txt:2:1:safe, fast, safe\npoem.txt:2:13:safe, fast, safe\n",
+            print(options, false)
+        );
+    }
+
+    #[test]
+    fn json() {
+        let options = PrintOptions {
+            json: true,
+            after_context: 1,
+            ..Default::default()
+        };
+        let mut printer = Printer::new(Vec::new(), &options, true);
+        printer.begin_file();
+        let searcher = Searcher {
+            after_context: 1,
+            ..Default::default()
+        };
+        let contents: &[u8] = b"say \"hi\"\n\tbye\n";
+        let path = Path::new("a.txt");
+        searcher
+            .search_reader(&Substring::new("hi"), contents, path, &mut printer)
+            .unwrap();
+        printer.end_file(Path::new("a.txt")).unwrap();
+        // Inputs without anything printed have no events.
+        printer.begin_file();
+        printer.end_file(Path::new("b.txt")).unwrap();
+
+        let stats = Stats {
+            searches: 2,
+            searches_with_match: 1,
+            matched_lines: 1,
+            matches: 1,
+        };
+        assert_eq!(stats, printer.stats());
+        assert_eq!(
+            "{\"type\":\"begin\",\"data\":{\"path\":\"a.txt\"}}
+{\"type\":\"match\",\"data\":{\"path\":\"a.txt\",\"line_number\":1,\"byte_offset\":0,\
+\"line\":\"say \\\"hi\\\"\",\"submatches\":[{\"match\":\"hi\",\"start\":5,\"end\":7}]}}
+{\"type\":\"context\",\"data\":{\"path\":\"a.txt\",\"line_number\":2,\"byte_offset\":9,\
+\"line\":\"\\tbye\"}}
+{\"type\":\"end\",\"data\":{\"path\":\"a.txt\",\"stats\":{\"matched_lines\":1,\"matches\":1}}}
+",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+}
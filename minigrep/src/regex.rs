@@ -0,0 +1,1040 @@
+// A small regular expression engine used by the --regex mode.
+// The pattern is parsed into a syntax tree, compiled into a flat list of
+// instructions and then executed by a Pike VM. The VM follows every possible
+// path through the pattern at the same time, so matching stays linear in the
+// length of the input, unlike a backtracking engine which can blow up on
+// patterns such as (a*)*b.
+
+use std::fmt;
+
+use crate::casefold;
+
+// The maximum count allowed in a {n,m} repetition. Repetitions are expanded
+// into copies of the repeated expression, so the limit keeps programs small.
+const MAX_REPEAT: u32 = 1000;
+
+// The maximum number of instructions in a program. Nested repetitions
+// multiply, so ((a{1000}){1000}){1000} would otherwise need a billion.
+const MAX_PROGRAM_SIZE: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnclosedGroup,
+    UnopenedGroup,
+    UnclosedClass,
+    InvalidClassRange,
+    NothingToRepeat,
+    InvalidRepetition,
+    TrailingBackslash,
+    UnknownEscape,
+    UnsupportedGroup,
+    TooLarge,
+}
+
+impl ErrorKind {
+    // The message for the kind, which Display follows with the position.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::UnclosedGroup => "Invalid regular expression: unclosed group",
+            ErrorKind::UnopenedGroup => "Invalid regular expression: unopened group",
+            ErrorKind::UnclosedClass => "Invalid regular expression: unclosed character class",
+            ErrorKind::InvalidClassRange => "Invalid regular expression: invalid class range",
+            ErrorKind::NothingToRepeat => "Invalid regular expression: nothing to repeat",
+            ErrorKind::InvalidRepetition => "Invalid regular expression: invalid repetition",
+            ErrorKind::TrailingBackslash => "Invalid regular expression: trailing backslash",
+            ErrorKind::UnknownEscape => "Invalid regular expression: unknown escape sequence",
+            ErrorKind::UnsupportedGroup => "Invalid regular expression: unsupported group flag",
+            ErrorKind::TooLarge => "Invalid regular expression: pattern too large",
+        }
+    }
+}
+
+// The position is the character index into the pattern where parsing failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub position: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.kind.as_str(), self.position)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Shorthand classes such as \d, \w and \s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_numeric(),
+            Perl::Word => is_word_char(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let found = if ignore_case {
+            case_variants(c).any(|v| self.contains(v))
+        } else {
+            self.contains(c)
+        };
+        found != self.negated
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.items.iter().any(|item| match item {
+            ClassItem::Range(low, high) => *low <= c && c <= *high,
+            ClassItem::Perl(perl, negated) => perl.matches(c) != *negated,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+    // The edges of a whole word match: no word character right before or
+    // right after the position.
+    NoWordBefore,
+    NoWordAfter,
+}
+
+// What the patterns as a whole must line up with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounds {
+    None,
+    Word,
+    Line,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            position: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.parse_alternate()?;
+        match self.peek() {
+            None => Ok(node),
+            Some(_) => Err(self.error(ErrorKind::UnopenedGroup)),
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repeat(atom)?);
+        }
+
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_repeat(&mut self, mut atom: Node) -> Result<Node, Error> {
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.parse_counted()? {
+                    Some(bounds) => bounds,
+                    // Not a valid {n,m}, so the brace is a plain literal.
+                    None => return Ok(atom),
+                },
+                _ => return Ok(atom),
+            };
+            if start == self.pos {
+                self.pos += 1;
+            }
+
+            if matches!(atom, Node::Empty | Node::Assert(_)) {
+                self.pos = start;
+                return Err(self.error(ErrorKind::NothingToRepeat));
+            }
+
+            let greedy = !self.eat('?');
+            atom = Node::Repeat {
+                node: Box::new(atom),
+                min,
+                max,
+                greedy,
+            };
+        }
+    }
+
+    // Parses {n}, {n,} or {n,m}. Returns None, without consuming anything,
+    // when the brace does not start a repetition.
+    fn parse_counted(&mut self) -> Result<Option<(u32, Option<u32>)>, Error> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let min = self.parse_number();
+        let max = if self.eat(',') {
+            self.parse_number()
+        } else {
+            min
+        };
+
+        let bounds = match (min, self.eat('}')) {
+            (Some(min), true) => (min, max),
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+
+        if bounds.0 > MAX_REPEAT
+            || bounds
+                .1
+                .is_some_and(|max| max > MAX_REPEAT || max < bounds.0)
+        {
+            self.pos = start;
+            return Err(self.error(ErrorKind::InvalidRepetition));
+        }
+        Ok(Some(bounds))
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        // Overflowing numbers are clamped so they are reported as too large.
+        Some(digits.parse().unwrap_or(u32::MAX))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, Error> {
+        let c = self.peek().unwrap();
+        match c {
+            '(' => self.parse_group(),
+            '[' => self.parse_class(),
+            '*' | '+' | '?' => Err(self.error(ErrorKind::NothingToRepeat)),
+            '.' => {
+                self.pos += 1;
+                Ok(Node::Any)
+            }
+            '^' => {
+                self.pos += 1;
+                Ok(Node::Assert(Assertion::Start))
+            }
+            '$' => {
+                self.pos += 1;
+                Ok(Node::Assert(Assertion::End))
+            }
+            '\\' => self.parse_escape(),
+            _ => {
+                self.pos += 1;
+                Ok(Node::Literal(c))
+            }
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, Error> {
+        let open = self.pos;
+        self.pos += 1;
+
+        let index = if self.eat('?') {
+            if !self.eat(':') {
+                return Err(self.error(ErrorKind::UnsupportedGroup));
+            }
+            None
+        } else {
+            self.groups += 1;
+            Some(self.groups)
+        };
+
+        let node = self.parse_alternate()?;
+        if !self.eat(')') {
+            return Err(Error {
+                kind: ErrorKind::UnclosedGroup,
+                position: open,
+            });
+        }
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, Error> {
+        self.pos += 1;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(ErrorKind::TrailingBackslash)),
+        };
+
+        let node = match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            _ => match self.escape_item(c)? {
+                ClassItem::Range(c, _) => Node::Literal(c),
+                perl => Node::Class(Class {
+                    items: vec![perl],
+                    negated: false,
+                }),
+            },
+        };
+        self.pos += 1;
+        Ok(node)
+    }
+
+    // Escapes that are valid both inside and outside of a character class.
+    fn escape_item(&self, c: char) -> Result<ClassItem, Error> {
+        let item = match c {
+            'd' => ClassItem::Perl(Perl::Digit, false),
+            'D' => ClassItem::Perl(Perl::Digit, true),
+            'w' => ClassItem::Perl(Perl::Word, false),
+            'W' => ClassItem::Perl(Perl::Word, true),
+            's' => ClassItem::Perl(Perl::Space, false),
+            'S' => ClassItem::Perl(Perl::Space, true),
+            'n' => ClassItem::Range('\n', '\n'),
+            't' => ClassItem::Range('\t', '\t'),
+            'r' => ClassItem::Range('\r', '\r'),
+            // Any punctuation can be escaped to match it literally.
+            c if !c.is_alphanumeric() => ClassItem::Range(c, c),
+            _ => return Err(self.error(ErrorKind::UnknownEscape)),
+        };
+        Ok(item)
+    }
+
+    fn parse_class(&mut self) -> Result<Node, Error> {
+        let open = self.pos;
+        self.pos += 1;
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    return Err(Error {
+                        kind: ErrorKind::UnclosedClass,
+                        position: open,
+                    })
+                }
+            };
+            // A ']' right after the opening bracket is a literal.
+            if c == ']' && !items.is_empty() {
+                self.pos += 1;
+                break;
+            }
+
+            let low = self.parse_class_char()?;
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']');
+
+            match low {
+                ClassItem::Range(low, _) if is_range => {
+                    self.pos += 1;
+                    let high = match self.parse_class_char()? {
+                        ClassItem::Range(high, _) => high,
+                        ClassItem::Perl(..) => return Err(self.error(ErrorKind::InvalidClassRange)),
+                    };
+                    if high < low {
+                        return Err(self.error(ErrorKind::InvalidClassRange));
+                    }
+                    items.push(ClassItem::Range(low, high));
+                }
+                item => items.push(item),
+            }
+        }
+
+        Ok(Node::Class(Class { items, negated }))
+    }
+
+    fn parse_class_char(&mut self) -> Result<ClassItem, Error> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(ErrorKind::UnclosedClass)),
+        };
+        self.pos += 1;
+        if c != '\\' {
+            return Ok(ClassItem::Range(c, c));
+        }
+
+        match self.peek() {
+            Some(escaped) => {
+                let item = self.escape_item(escaped)?;
+                self.pos += 1;
+                Ok(item)
+            }
+            None => Err(self.error(ErrorKind::TrailingBackslash)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Literal(c) => {
+                self.push(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.push(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.push(Inst::Class(class.clone()));
+            }
+            Node::Assert(assertion) => {
+                self.push(Inst::Assert(*assertion));
+            }
+            Node::Group(node, None) => self.compile(node),
+            Node::Group(node, Some(index)) => {
+                self.push(Inst::Save(index * 2));
+                self.compile(node);
+                self.push(Inst::Save(index * 2 + 1));
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node);
+                }
+            }
+            Node::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i == branches.len() - 1 {
+                        self.compile(branch);
+                        break;
+                    }
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(branch);
+                    jumps.push(self.push(Inst::Jmp(0)));
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.push(Inst::Jmp(split));
+                        self.patch_split(split, *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        for split in splits {
+                            self.patch_split(split, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Points a split at the instruction after it and the current end of the
+    // program. Greedy repetitions prefer to keep going, lazy ones to stop.
+    fn patch_split(&mut self, split: usize, greedy: bool) {
+        let end = self.program.len();
+        self.program[split] = if greedy {
+            Inst::Split(split + 1, end)
+        } else {
+            Inst::Split(end, split + 1)
+        };
+    }
+}
+
+// The number of instructions Compiler::compile emits for node, saturating
+// instead of overflowing.
+fn program_size(node: &Node) -> usize {
+    match node {
+        Node::Empty => 0,
+        Node::Literal(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+        Node::Group(node, None) => program_size(node),
+        Node::Group(node, Some(_)) => program_size(node).saturating_add(2),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .fold(0, |size, node| size.saturating_add(program_size(node))),
+        // A split and a jump around every branch but the last.
+        Node::Alternate(branches) => branches.iter().fold(0, |size, branch| {
+            size.saturating_add(program_size(branch)).saturating_add(2)
+        }),
+        Node::Repeat { node, min, max, .. } => {
+            let size = program_size(node);
+            let optional = match max {
+                None => size.saturating_add(2),
+                Some(max) => size.saturating_add(1).saturating_mul((max - min) as usize),
+            };
+            size.saturating_mul(*min as usize).saturating_add(optional)
+        }
+    }
+}
+
+// Capture slots hold byte offsets, two per group with group 0 being the
+// whole match.
+type Slots = Vec<Option<usize>>;
+
+struct Threads {
+    // dense holds the program counters in priority order, with sparse
+    // pointing into it for constant time membership checks.
+    dense: Vec<(usize, Slots)>,
+    sparse: Vec<usize>,
+    visited: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            dense: Vec::new(),
+            sparse: vec![0; size],
+            visited: Vec::new(),
+        }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        i < self.visited.len() && self.visited[i] == pc
+    }
+
+    fn insert(&mut self, pc: usize) {
+        self.sparse[pc] = self.visited.len();
+        self.visited.push(pc);
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+        self.visited.clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    slots: usize,
+    ignore_case: bool,
+}
+
+impl Regex {
+    pub fn build(pattern: &str, ignore_case: bool) -> Result<Regex, Error> {
+        Regex::build_many(&[pattern], ignore_case)
+    }
+
+    // Matches wherever any of the patterns match, as if they were joined
+    // with '|'. Capture groups are numbered across all the patterns.
+    pub fn build_many<P: AsRef<str>>(patterns: &[P], ignore_case: bool) -> Result<Regex, Error> {
+        Regex::build_with(patterns, ignore_case, Bounds::None)
+    }
+
+    // Like build_many, but a match can't have a word character right before
+    // or after it. The edges are part of the program, so an alternative
+    // that isn't a whole word gives way to a longer one that is.
+    pub fn build_many_whole_word<P: AsRef<str>>(
+        patterns: &[P],
+        ignore_case: bool,
+    ) -> Result<Regex, Error> {
+        Regex::build_with(patterns, ignore_case, Bounds::Word)
+    }
+
+    // Like build_many, but the patterns must match the whole text, as if
+    // they were wrapped in ^(?:...)$.
+    pub fn build_many_whole_line<P: AsRef<str>>(
+        patterns: &[P],
+        ignore_case: bool,
+    ) -> Result<Regex, Error> {
+        Regex::build_with(patterns, ignore_case, Bounds::Line)
+    }
+
+    fn build_with<P: AsRef<str>>(
+        patterns: &[P],
+        ignore_case: bool,
+        bounds: Bounds,
+    ) -> Result<Regex, Error> {
+        let mut groups = 0;
+        let mut branches = Vec::new();
+        for pattern in patterns {
+            let mut parser = Parser {
+                chars: pattern.as_ref().chars().collect(),
+                pos: 0,
+                groups,
+            };
+            branches.push(parser.parse()?);
+            groups = parser.groups;
+        }
+        let mut node = match branches.len() {
+            // No patterns at all, from an empty -f file, match nothing.
+            0 => Node::Class(Class {
+                items: Vec::new(),
+                negated: false,
+            }),
+            1 => branches.pop().unwrap(),
+            _ => Node::Alternate(branches),
+        };
+        let edges = match bounds {
+            Bounds::None => None,
+            Bounds::Word => Some((Assertion::NoWordBefore, Assertion::NoWordAfter)),
+            Bounds::Line => Some((Assertion::Start, Assertion::End)),
+        };
+        if let Some((before, after)) = edges {
+            node = Node::Concat(vec![
+                Node::Assert(before),
+                Node::Group(Box::new(node), None),
+                Node::Assert(after),
+            ]);
+        }
+
+        // The size is worked out before compiling, which would take as long
+        // as the program is large.
+        if program_size(&node) > MAX_PROGRAM_SIZE {
+            return Err(Error {
+                kind: ErrorKind::TooLarge,
+                position: 0,
+            });
+        }
+        let mut compiler = Compiler {
+            program: vec![Inst::Save(0)],
+        };
+        compiler.compile(&node);
+        compiler.push(Inst::Save(1));
+        compiler.push(Inst::Match);
+
+        Ok(Regex {
+            program: compiler.program,
+            slots: (groups + 1) * 2,
+            ignore_case,
+        })
+    }
+
+    // The number of capture groups, including the implicit whole match group.
+    pub fn captures_len(&self) -> usize {
+        self.slots / 2
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    // Finds the leftmost match starting the search at byte offset start.
+    // Assertions still see the text before start, so ^ will not match there.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let slots = self.exec(text, start)?;
+        Some((slots[0]?, slots[1]?))
+    }
+
+    // Returns the byte range of every group, None for groups that did not
+    // take part in the match.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<(usize, usize)>>> {
+        let slots = self.exec(text, start)?;
+        Some(
+            slots
+                .chunks(2)
+                .map(|pair| match (pair[0], pair[1]) {
+                    (Some(start), Some(end)) => Some((start, end)),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    // All non-overlapping matches, from left to right.
+    pub fn find_iter(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start <= text.len() {
+            let (from, to) = match self.find_at(text, start) {
+                Some(found) => found,
+                None => break,
+            };
+            matches.push((from, to));
+
+            // Step over empty matches so the search always makes progress.
+            start = if to == from {
+                match text[to..].chars().next() {
+                    Some(c) => to + c.len_utf8(),
+                    None => break,
+                }
+            } else {
+                to
+            };
+        }
+        matches
+    }
+
+    fn exec(&self, text: &str, start: usize) -> Option<Slots> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            // Start a new attempt at every position until something matches.
+            // It has the lowest priority, so earlier starts win.
+            if matched.is_none() {
+                self.add_thread(&mut current, 0, vec![None; self.slots], text, pos);
+            }
+            if matched.is_some() && current.dense.is_empty() {
+                break;
+            }
+
+            let c = text[pos..].chars().next();
+            for (pc, slots) in current.dense.drain(..) {
+                let step = match &self.program[pc] {
+                    Inst::Char(expected) => c.is_some_and(|c| self.char_eq(*expected, c)),
+                    Inst::Any => c.is_some_and(|c| c != '\n'),
+                    Inst::Class(class) => c.is_some_and(|c| class.matches(c, self.ignore_case)),
+                    Inst::Match => {
+                        // Lower priority threads are cut off by the match.
+                        matched = Some(slots);
+                        break;
+                    }
+                    _ => false,
+                };
+                if step {
+                    let after = pos + c.unwrap().len_utf8();
+                    self.add_thread(&mut next, pc + 1, slots, text, after);
+                }
+            }
+            current.clear();
+
+            match c {
+                Some(c) => pos += c.len_utf8(),
+                None => break,
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        matched
+    }
+
+    // Follows jumps, splits, saves and assertions so the list only holds
+    // threads waiting on a character or a match. The instructions still to
+    // follow are kept on a stack of their own rather than by recursing, a
+    // large program would otherwise run out of stack.
+    fn add_thread(&self, list: &mut Threads, pc: usize, slots: Slots, text: &str, pos: usize) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if list.contains(pc) {
+                continue;
+            }
+            list.insert(pc);
+
+            match &self.program[pc] {
+                Inst::Jmp(target) => stack.push((*target, slots)),
+                // The second branch is pushed first, so the first is followed first.
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                }
+                Inst::Save(slot) => {
+                    slots[*slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion_holds(*assertion, text, pos) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                _ => list.dense.push((pc, slots)),
+            }
+        }
+    }
+
+    fn char_eq(&self, expected: char, c: char) -> bool {
+        expected == c || (self.ignore_case && casefold::fold(expected) == casefold::fold(c))
+    }
+}
+
+// ^ and $ match at the start and end of every line, which is the same as
+// the start and end of the text when a single line is searched. With
+// --multiline they still anchor to lines, a line ending in "\r\n" included.
+fn assertion_holds(assertion: Assertion, text: &str, pos: usize) -> bool {
+    match assertion {
+        Assertion::Start => pos == 0 || text.as_bytes()[pos - 1] == b'\n',
+        Assertion::End => {
+            let rest = &text[pos..];
+            rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n")
+        }
+        Assertion::WordBoundary | Assertion::NotWordBoundary => {
+            let before = text[..pos].chars().next_back().is_some_and(is_word_char);
+            let after = text[pos..].chars().next().is_some_and(is_word_char);
+            (before != after) == (assertion == Assertion::WordBoundary)
+        }
+        Assertion::NoWordBefore => !text[..pos].chars().next_back().is_some_and(is_word_char),
+        Assertion::NoWordAfter => !text[pos..].chars().next().is_some_and(is_word_char),
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// The character itself followed by its case folding and its single
+// character lower and upper case forms, if it has any.
+fn case_variants(c: char) -> impl Iterator<Item = char> {
+    let lower = c.to_lowercase();
+    let upper = c.to_uppercase();
+    let lower = if lower.len() == 1 { lower.last() } else { None };
+    let upper = if upper.len() == 1 { upper.last() } else { None };
+    [c, casefold::fold(c)].into_iter().chain(lower).chain(upper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::build(pattern, false).unwrap().find(text)
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(Some((3, 6)), find("fn ", "pubfn x"));
+        assert_eq!(Some((0, 8)), find(r"fn \w+\(", "fn main() {"));
+        assert_eq!(Some((1, 4)), find("[0-9]+", "a123b"));
+        assert_eq!(Some((0, 1)), find("[^a-c]", "d"));
+        assert_eq!(None, find("[^a-c]", "abc"));
+        assert_eq!(Some((0, 2)), find(r"[\d\]]+", "1]x"));
+    }
+
+    #[test]
+    fn alternation_and_groups() {
+        let regex = Regex::build("(foo|ba(r|z))+", false).unwrap();
+        assert_eq!(3, regex.captures_len());
+        let caps = regex.captures_at("xbazfoo!", 0).unwrap();
+        assert_eq!(Some((1, 7)), caps[0]);
+        assert_eq!(Some((4, 7)), caps[1]);
+        assert_eq!(Some((3, 4)), caps[2]);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(Some((0, 3)), find("a{2,3}", "aaaa"));
+        assert_eq!(Some((0, 2)), find("a{2}", "aaaa"));
+        assert_eq!(Some((0, 1)), find("a+?", "aaa"));
+        assert_eq!(Some((0, 6)), find("<.*>", "<a><b>"));
+        assert_eq!(Some((0, 3)), find("<.*?>", "<a><b>"));
+        // Braces that are not a repetition are plain characters.
+        assert_eq!(Some((7, 8)), find("{", "main() {"));
+        // Nested stars terminate instead of looping forever.
+        assert_eq!(None, find("(a*)*b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+    }
+
+    #[test]
+    fn anchors_and_boundaries() {
+        assert_eq!(Some((0, 3)), find("^foo", "foofoo"));
+        assert_eq!(Some((3, 6)), find("foo$", "foofoo"));
+        assert_eq!(None, find("^bar", "foobar"));
+        assert_eq!(Some((4, 8)), find(r"\bduct\b", "pro duct"));
+        assert_eq!(None, find(r"\bduct\b", "productive"));
+        // Across lines they anchor to each line, and . stays on one.
+        assert_eq!(Some((4, 7)), find("^bar", "foo\nbar"));
+        assert_eq!(Some((0, 3)), find("foo$", "foo\r\nbar"));
+        assert_eq!(Some((2, 7)), find(r"o\nb.r", "foo\nbar"));
+        assert_eq!(None, find("o.b", "foo\nbar"));
+    }
+
+    #[test]
+    fn whole_line() {
+        let regex = Regex::build_many_whole_line(&["a|ab", "x+"], false).unwrap();
+        assert_eq!(Some((0, 2)), regex.find("ab"));
+        assert_eq!(Some((0, 3)), regex.find("xxx"));
+        assert_eq!(None, regex.find("abc"));
+        assert_eq!(None, regex.find("cxx"));
+    }
+
+    #[test]
+    fn no_patterns() {
+        let regex = Regex::build_many::<&str>(&[], false).unwrap();
+        assert_eq!(Vec::<(usize, usize)>::new(), regex.find_iter("abc"));
+        assert_eq!(None, regex.find(""));
+    }
+
+    #[test]
+    fn whole_word() {
+        let regex = Regex::build_many_whole_word(&["du|duct", "foo"], false).unwrap();
+        assert_eq!(Some((0, 4)), regex.find("duct"));
+        assert_eq!(vec![(4, 6), (12, 15)], regex.find_iter("dub du foo_ foo"));
+        assert_eq!(Some((1, 4)), regex.find("-foo-"));
+        assert_eq!(None, regex.find("foobar"));
+    }
+
+    #[test]
+    fn ignore_case() {
+        let regex = Regex::build("rust[a-z]*", true).unwrap();
+        assert_eq!(Some((1, 5)), regex.find("TRUST"));
+        assert_eq!(vec![(0, 4), (7, 11)], regex.find_iter("Rust & rust"));
+    }
+
+    #[test]
+    fn many_patterns() {
+        let regex = Regex::build_many(&["(a)b", r"c(\d)"], false).unwrap();
+        assert_eq!(3, regex.captures_len());
+        assert_eq!(vec![(1, 3), (4, 6)], regex.find_iter("xab c1"));
+        let caps = regex.captures_at("c7", 0).unwrap();
+        assert_eq!(vec![Some((0, 2)), None, Some((1, 2))], caps);
+    }
+
+    #[test]
+    fn find_iter_empty_matches() {
+        let regex = Regex::build("x*", false).unwrap();
+        assert_eq!(vec![(0, 0), (1, 2), (2, 2)], regex.find_iter("ax"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let kind = |pattern| Regex::build(pattern, false).unwrap_err().kind;
+        assert_eq!(ErrorKind::UnclosedGroup, kind("(ab"));
+        assert_eq!(ErrorKind::UnopenedGroup, kind("ab)"));
+        assert_eq!(ErrorKind::UnclosedClass, kind("[ab"));
+        assert_eq!(ErrorKind::InvalidClassRange, kind("[z-a]"));
+        assert_eq!(ErrorKind::NothingToRepeat, kind("*a"));
+        assert_eq!(ErrorKind::InvalidRepetition, kind("a{3,2}"));
+        assert_eq!(ErrorKind::TrailingBackslash, kind("ab\\"));
+        assert_eq!(ErrorKind::UnknownEscape, kind(r"\q"));
+        assert_eq!(ErrorKind::UnsupportedGroup, kind("(?=a)"));
+        assert_eq!(ErrorKind::TooLarge, kind("((a{1000}){1000}){1000}"));
+        assert_eq!(ErrorKind::TooLarge, kind("(a{2,1000}){1000}"));
+        assert!(Regex::build("(a{1,100}){100}", false).is_ok());
+    }
+
+    #[test]
+    fn deep_programs() {
+        // Following the empty branches of every a? takes thousands of steps
+        // at once, which must not need as much stack.
+        let regex = Regex::build("(?:(?:a?){1000}){49}b", false).unwrap();
+        let found = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || regex.find("aab"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(Some((0, 3)), found);
+
+        let err = Regex::build("a(b", false).unwrap_err();
+        assert_eq!(1, err.position);
+        assert_eq!(
+            "Invalid regular expression: unclosed group at position 1",
+            err.to_string()
+        );
+    }
+}
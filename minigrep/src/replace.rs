@@ -0,0 +1,384 @@
+// Search and replace. The replacement text may refer to the groups of a
+// regular expression match: $0 is the whole match, $1 the first group and so
+// on, ${1} keeps the number apart from text following it and $$ is a plain
+// '$'. With --in-place the files are rewritten, by writing the new contents
+// to a temporary file next to the original and renaming it over it, so a
+// file is never left half written.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::matcher::Matcher;
+use crate::printer::Printer;
+use crate::regex::Regex;
+use crate::{Config, Finder, OutputMode, READ_BUFFER_SIZE};
+
+// The number of unchanged lines shown around every change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Group(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+impl Replacement {
+    pub fn parse(text: &str) -> Replacement {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+
+        while let Some(dollar) = rest.find('$') {
+            literal.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            // ${N} or $N, anything else keeps the '$' as it is.
+            let (digits, after) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) if is_number(&braced[..end]) => (&braced[..end], &braced[end + 1..]),
+                    _ => ("", rest),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+
+            match digits.parse() {
+                Ok(group) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Group(group));
+                    rest = after;
+                }
+                Err(_) => {
+                    literal.push('$');
+                    // $$ is an escaped '$'.
+                    if let Some(after) = rest.strip_prefix('$') {
+                        rest = after;
+                    }
+                }
+            }
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Replacement { parts }
+    }
+
+    // The highest group referred to, 0 when only the whole match is used.
+    pub fn max_group(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Group(group) => *group,
+                Part::Literal(_) => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Replaces the spans of the line. The groups are looked up with the
+    // regex when there is one, without it only $0 refers to anything.
+    // Returns the new line and the spans of the replaced text in it.
+    pub fn apply(
+        &self,
+        line: &str,
+        spans: &[(usize, usize)],
+        regex: Option<&Regex>,
+    ) -> (String, Vec<(usize, usize)>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut new_spans = Vec::with_capacity(spans.len());
+        let mut copied = 0;
+
+        for (start, end) in spans {
+            replaced.push_str(&line[copied..*start]);
+            let groups = match regex {
+                Some(regex) => regex.captures_at(line, *start).unwrap_or_default(),
+                None => vec![Some((*start, *end))],
+            };
+
+            let replaced_start = replaced.len();
+            for part in &self.parts {
+                match part {
+                    Part::Literal(text) => replaced.push_str(text),
+                    Part::Group(group) => {
+                        if let Some(Some((from, to))) = groups.get(*group) {
+                            replaced.push_str(&line[*from..*to]);
+                        }
+                    }
+                }
+            }
+            new_spans.push((replaced_start, replaced.len()));
+            copied = *end;
+        }
+
+        replaced.push_str(&line[copied..]);
+        (replaced, new_spans)
+    }
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+// Applies the replacement to every matching line of the file. With --diff
+// the changes are printed as a unified diff, and with --in-place the result
+// is written back. Returns the number of matching lines.
+pub(crate) fn rewrite<W: Write>(
+    config: &Config,
+    finder: &Finder,
+    replacement: &Replacement,
+    path: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let contents = fs::read(path)?;
+    let max_count = config.max_count.unwrap_or(usize::MAX);
+    // Binary files are left alone, replacing text in them would likely break
+    // them. A match in one is still told about, the same as when searching.
+    if !config.text && crate::is_binary(&contents[..contents.len().min(READ_BUFFER_SIZE)]) {
+        let text = String::from_utf8_lossy(&contents);
+        let count = text
+            .lines()
+            .filter(|line| finder.find_iter(line).is_empty() == config.invert)
+            .take(max_count)
+            .count();
+        if count > 0 && config.mode == OutputMode::Lines {
+            printer.print_binary_unchanged(path)?;
+        }
+        return Ok(count);
+    }
+    let mut old_lines = Vec::new();
+    let mut new_lines = Vec::new();
+    let mut new_contents = Vec::with_capacity(contents.len());
+    let mut count = 0;
+
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        let ending = &line[text.len()..];
+
+        // Lines that aren't valid UTF-8 are kept as they are, rather than
+        // writing back replacement characters.
+        let old = String::from_utf8_lossy(text);
+        let mut new = None;
+        if let Ok(text) = std::str::from_utf8(text) {
+            let spans = finder.find_iter(text);
+            if spans.is_empty() == config.invert && count < max_count {
+                count += 1;
+                if !spans.is_empty() {
+                    new = Some(replacement.apply(text, &spans, config.regex.as_ref()).0);
+                }
+            }
+        }
+
+        match &new {
+            Some(new) => new_contents.extend_from_slice(new.as_bytes()),
+            None => new_contents.extend_from_slice(text),
+        }
+        new_contents.extend_from_slice(ending);
+        new_lines.push(new.unwrap_or_else(|| old.to_string()));
+        old_lines.push(old);
+    }
+
+    if new_contents == contents {
+        return Ok(count);
+    }
+    if config.diff {
+        let old_lines: Vec<&str> = old_lines.iter().map(|line| line.as_ref()).collect();
+        printer.print_diff(&unified_diff(path, &old_lines, &new_lines))?;
+    }
+    if config.in_place {
+        replace_file(path, &new_contents)?;
+    }
+    Ok(count)
+}
+
+// Writes the contents to a temporary file in the same directory, so the
+// rename stays on one file system and is atomic, then renames it over path.
+// A file that doesn't exist yet is created. A symlink is followed, so the
+// file it points to is replaced rather than the link.
+pub(crate) fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = &match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let temp: PathBuf = path.with_file_name(format!(".{name}.minigrep-{}", process::id()));
+    let permissions = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()?;
+            match permissions {
+                Some(permissions) => fs::set_permissions(&temp, permissions),
+                None => Ok(()),
+            }
+        })
+        .and_then(|_| fs::rename(&temp, path));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+// A unified diff between two versions of a file with the same number of
+// lines, as replacing text within lines never adds or removes any.
+pub fn unified_diff(path: &Path, old: &[&str], new: &[String]) -> String {
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    let mut diff = format!("--- a/{0}\n+++ b/{0}\n", path.display());
+
+    let mut next = 0;
+    while next < changed.len() {
+        // Changes closer than twice the context share a hunk.
+        let first = changed[next];
+        let mut last = first;
+        while next < changed.len() && changed[next] <= last + 2 * DIFF_CONTEXT + 1 {
+            last = changed[next];
+            next += 1;
+        }
+
+        let start = first.saturating_sub(DIFF_CONTEXT);
+        let end = (last + DIFF_CONTEXT + 1).min(old.len());
+        let len = end - start;
+        diff.push_str(&format!("@@ -{0},{len} +{0},{len} @@\n", start + 1));
+
+        let mut i = start;
+        while i < end {
+            if old[i] == new[i] {
+                diff.push_str(&format!(" {}\n", old[i]));
+                i += 1;
+                continue;
+            }
+            // A run of changed lines shows all the old lines, then the new ones.
+            let run_end = (i..end).find(|&j| old[j] == new[j]).unwrap_or(end);
+            for line in &old[i..run_end] {
+                diff.push_str(&format!("-{line}\n"));
+            }
+            for line in &new[i..run_end] {
+                diff.push_str(&format!("+{line}\n"));
+            }
+            i = run_end;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace(replacement: &str, line: &str, pattern: &str) -> String {
+        let regex = Regex::build(pattern, false).unwrap();
+        let spans = regex.find_iter(line);
+        Replacement::parse(replacement)
+            .apply(line, &spans, Some(&regex))
+            .0
+    }
+
+    #[test]
+    fn group_references() {
+        assert_eq!("b=a", replace("$2=$1", "a=b", r"(\w)=(\w)"));
+        assert_eq!("[ab]x", replace("[$0]", "abx", "ab"));
+        assert_eq!("a1b-", replace("${1}1b$2", "a-", "(a)(-)?"));
+        assert_eq!("$5 $x", replace("$$5 $x", "cost", "cost"));
+        assert_eq!(2, Replacement::parse("$1${2}").max_group());
+    }
+
+    #[test]
+    fn replaced_spans() {
+        let replacement = Replacement::parse("fn");
+        assert_eq!(
+            (String::from("fn x, fn y"), vec![(0, 2), (6, 8)]),
+            replacement.apply("func x, func y", &[(0, 4), (8, 12)], None)
+        );
+    }
+
+    #[test]
+    fn diff_hunks() {
+        let old: Vec<String> = (1..=12).map(|i| i.to_string()).collect();
+        let old: Vec<&str> = old.iter().map(|line| line.as_str()).collect();
+        let mut new: Vec<String> = old.iter().map(|line| line.to_string()).collect();
+        new[0] = String::from("one");
+        new[1] = String::from("two");
+        new[11] = String::from("twelve");
+
+        assert_eq!(
+            "--- a/n.txt\n+++ b/n.txt\n\
+@@ -1,5 +1,5 @@\n-1\n-2\n+one\n+two\n 3\n 4\n 5\n\
+@@ -9,4 +9,4 @@\n 9\n 10\n 11\n-12\n+twelve\n",
+            unified_diff(Path::new("n.txt"), &old, &new)
+        );
+    }
+
+    #[test]
+    fn binary_files_are_left_unchanged() {
+        let path = std::env::temp_dir().join(format!("minigrep-binary-{}", process::id()));
+        fs::write(&path, "old\0\nold\n").unwrap();
+        let args = [
+            "--replace",
+            "new",
+            "--in-place",
+            "old",
+            path.to_str().unwrap(),
+        ];
+        let args = ["minigrep"].iter().chain(&args).map(|arg| arg.to_string());
+        let config = Config::build(args).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config.print, false);
+
+        let replacement = config.replace.as_ref().unwrap();
+        let finder = Finder::new(&config);
+        assert_eq!(
+            2,
+            rewrite(&config, &finder, replacement, &path, &mut printer).unwrap()
+        );
+        let note = format!("Binary file {} matches, left unchanged\n", path.display());
+        assert_eq!(note, String::from_utf8(printer.into_inner()).unwrap());
+        assert_eq!("old\0\nold\n", fs::read_to_string(&path).unwrap());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_symlink_targets() {
+        let dir = std::env::temp_dir().join(format!("minigrep-symlink-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (target, link) = (dir.join("r.txt"), dir.join("link.txt"));
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink("r.txt", &link).unwrap();
+
+        replace_file(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!("new", fs::read_to_string(&target).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,480 @@
+// Searching an input and handing the results over as events, for programs
+// using minigrep as a library that want the results in their own structures
+// instead of printed. A Searcher reads the input one line at a time, decides
+// which lines match and which are context around them, and passes them on
+// to a Sink:
+//  matched   a line selected by the search, with the spans of its matches
+//  context   a line before or after a match, without spans
+//  finish    the end of the input, with the number of matching lines
+// The printer is a Sink as well, that is how minigrep itself prints.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::literal;
+use crate::matcher::Matcher;
+use crate::mmap::Mmap;
+use crate::{Match, MMAP_THRESHOLD, READ_BUFFER_SIZE};
+
+pub trait Sink {
+    // Returns whether to go on with the input, false stops reading it.
+    fn matched(&mut self, path: &Path, found: &Match) -> io::Result<bool>;
+
+    fn context(&mut self, _path: &Path, _line: &Match) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, _path: &Path, _matched_lines: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decides which lines of an input are passed to the sink.
+#[derive(Debug, Clone, Default)]
+pub struct Searcher {
+    // Selects the lines that do not match instead. They have no spans.
+    pub invert: bool,
+    // Stop reading an input after this many matching lines.
+    pub max_count: Option<usize>,
+    // The number of lines to pass as context before and after every match.
+    pub before_context: usize,
+    pub after_context: usize,
+    // Matches may span lines, every line they cover is a matching line.
+    pub multiline: bool,
+}
+
+impl Searcher {
+    pub fn search_path<M: Matcher + ?Sized, S: Sink + ?Sized>(
+        &self,
+        matcher: &M,
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.is_file() && metadata.len() >= MMAP_THRESHOLD {
+            let map = Mmap::map(&file)?;
+            return self.search_slice(matcher, &map, path, sink);
+        }
+        let reader = BufReader::with_capacity(READ_BUFFER_SIZE, file);
+        self.search_reader(matcher, reader, path, sink)
+    }
+
+    // Searches a whole input in memory, such as a mapped file. When every
+    // match holds a literal string, the search skips from one occurrence of
+    // it to the next, and only the lines with one are given to the matcher.
+    // That leaves out the lines between them, so it isn't done for context
+    // or inverted matches.
+    pub fn search_slice<M: Matcher + ?Sized, S: Sink + ?Sized>(
+        &self,
+        matcher: &M,
+        contents: &[u8],
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        if self.multiline {
+            return self.search_multiline(matcher, contents, path, sink);
+        }
+        let context = self.before_context > 0 || self.after_context > 0;
+        let literal = match matcher.literal() {
+            Some(literal) if !self.invert && !context => literal,
+            _ => return self.search_reader(matcher, contents, path, sink),
+        };
+
+        let max_count = self.max_count.unwrap_or(usize::MAX);
+        let mut count = 0;
+        for (line_number, start, end) in literal::Lines::new(contents, literal.as_bytes()) {
+            if count == max_count {
+                break;
+            }
+            let line = &contents[start..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = String::from_utf8_lossy(line);
+
+            // The literal is only a candidate, the matcher decides.
+            let spans = matcher.find_iter(&line);
+            if spans.is_empty() {
+                continue;
+            }
+            count += 1;
+            let found = Match {
+                line_number,
+                byte_offset: start,
+                line: &line,
+                distances: distances(matcher, &line, &spans),
+                spans,
+            };
+            if !sink.matched(path, &found)? {
+                break;
+            }
+        }
+
+        sink.finish(path, count)?;
+        Ok(count)
+    }
+
+    // Searches the input one line at a time, so only the current line and
+    // the lines kept for before context are held in memory. Returns the
+    // number of matching lines. Multiline searches need the whole input, so
+    // it is read first.
+    pub fn search_reader<M: Matcher + ?Sized, S: Sink + ?Sized>(
+        &self,
+        matcher: &M,
+        mut reader: impl BufRead,
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        if self.multiline {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            return self.search_multiline(matcher, &contents, path, sink);
+        }
+
+        let mut lines = LineState::new(self);
+        let mut buffer = Vec::new();
+        let mut line_number = 0;
+        let mut byte_offset = 0;
+
+        while !lines.done() {
+            buffer.clear();
+            let read = reader.read_until(b'\n', &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+
+            let line = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            // Invalid UTF-8 is replaced instead of failing the whole input.
+            let line = String::from_utf8_lossy(line);
+
+            let spans = matcher.find_iter(&line);
+            let found = Match {
+                line_number,
+                byte_offset,
+                distances: distances(matcher, &line, &spans),
+                line: &line,
+                spans,
+            };
+            if !lines.line(path, sink, found)? {
+                break;
+            }
+            byte_offset += read;
+        }
+
+        sink.finish(path, lines.count)?;
+        Ok(lines.count)
+    }
+
+    // Searches the whole input at once, so a match may span several lines.
+    // Every line a match covers is a matching line, with the part of the
+    // match on it as its span, so the lines of a match are passed on one by
+    // one with their own line numbers, the same as in a line by line search.
+    fn search_multiline<M: Matcher + ?Sized, S: Sink + ?Sized>(
+        &self,
+        matcher: &M,
+        contents: &[u8],
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        let contents = String::from_utf8_lossy(contents);
+        let matches = matcher.find_iter(&contents);
+        // The matches don't overlap, so they end in order as well. Matches
+        // before first ended on an earlier line.
+        let mut first = 0;
+        let mut lines = LineState::new(self);
+        let mut byte_offset = 0;
+
+        for (index, raw) in contents.split_inclusive('\n').enumerate() {
+            if lines.done() {
+                break;
+            }
+            let next = byte_offset + raw.len();
+            let line = raw.strip_suffix('\n').unwrap_or(raw);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+
+            // An empty match at the start of the line is on it, any other
+            // match has to end after the start.
+            while first < matches.len() && {
+                let (start, end) = matches[first];
+                end < byte_offset || (end == byte_offset && start < end)
+            } {
+                first += 1;
+            }
+            let spans: Vec<(usize, usize)> = matches[first..]
+                .iter()
+                .take_while(|(start, _)| *start < next)
+                .map(|(start, end)| {
+                    let clip = |at: usize| at.clamp(byte_offset, byte_offset + line.len());
+                    (clip(*start) - byte_offset, clip(*end) - byte_offset)
+                })
+                .collect();
+
+            let found = Match {
+                line_number: index + 1,
+                byte_offset,
+                line,
+                distances: distances(matcher, line, &spans),
+                spans,
+            };
+            if !lines.line(path, sink, found)? {
+                break;
+            }
+            byte_offset = next;
+        }
+
+        sink.finish(path, lines.count)?;
+        Ok(lines.count)
+    }
+}
+
+// What a search remembers from one line to the next: the matching lines
+// counted so far and the context still to be passed on.
+struct LineState<'a> {
+    searcher: &'a Searcher,
+    max_count: usize,
+    count: usize,
+    // The last lines that weren't passed on, with their line number and
+    // byte offset, in case a match follows them.
+    before: VecDeque<(usize, usize, String)>,
+    after_remaining: usize,
+}
+
+impl<'a> LineState<'a> {
+    fn new(searcher: &'a Searcher) -> LineState<'a> {
+        LineState {
+            searcher,
+            max_count: searcher.max_count.unwrap_or(usize::MAX),
+            count: 0,
+            before: VecDeque::new(),
+            after_remaining: 0,
+        }
+    }
+
+    // After the last match, only its after context is still read.
+    fn done(&self) -> bool {
+        self.count == self.max_count && self.after_remaining == 0
+    }
+
+    // Passes the line on to the sink as a match or as context, or keeps it
+    // for before context. The line matches when it has spans. Returns
+    // whether to go on with the input.
+    fn line<S: Sink + ?Sized>(
+        &mut self,
+        path: &Path,
+        sink: &mut S,
+        mut found: Match,
+    ) -> io::Result<bool> {
+        let matched = found.spans.is_empty() == self.searcher.invert && self.count < self.max_count;
+        // Inverted matches have nothing to highlight.
+        if self.searcher.invert {
+            found.spans.clear();
+            found.distances.clear();
+        }
+
+        if matched {
+            self.count += 1;
+            while let Some((line_number, byte_offset, line)) = self.before.pop_front() {
+                let context = Match {
+                    line_number,
+                    byte_offset,
+                    line: &line,
+                    spans: Vec::new(),
+                    distances: Vec::new(),
+                };
+                sink.context(path, &context)?;
+            }
+            if !sink.matched(path, &found)? {
+                return Ok(false);
+            }
+            self.after_remaining = self.searcher.after_context;
+        } else if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            found.spans.clear();
+            found.distances.clear();
+            sink.context(path, &found)?;
+        } else if self.searcher.before_context > 0 {
+            if self.before.len() == self.searcher.before_context {
+                self.before.pop_front();
+            }
+            let kept = (found.line_number, found.byte_offset, found.line.to_string());
+            self.before.push_back(kept);
+        }
+        Ok(true)
+    }
+}
+
+// How far every span is from the query, when the matcher tells.
+pub(crate) fn distances<M: Matcher + ?Sized>(
+    matcher: &M,
+    line: &str,
+    spans: &[(usize, usize)],
+) -> Vec<usize> {
+    spans
+        .iter()
+        .filter_map(|(start, end)| matcher.distance(&line[*start..*end]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Substring;
+
+    // Collects the events as text, a line for each.
+    #[derive(Default)]
+    struct Events {
+        events: Vec<String>,
+        matches: usize,
+        // Stop after this many matches.
+        stop_after: Option<usize>,
+    }
+
+    impl Sink for Events {
+        fn matched(&mut self, _path: &Path, found: &Match) -> io::Result<bool> {
+            let event = format!("match {} {:?}", found.line_number, found.spans);
+            self.events.push(event);
+            self.matches += 1;
+            Ok(self.stop_after != Some(self.matches))
+        }
+
+        fn context(&mut self, _path: &Path, line: &Match) -> io::Result<()> {
+            self.events.push(format!("context {}", line.line_number));
+            Ok(())
+        }
+
+        fn finish(&mut self, _path: &Path, matched_lines: usize) -> io::Result<()> {
+            self.events.push(format!("finish {matched_lines}"));
+            Ok(())
+        }
+    }
+
+    fn events(searcher: Searcher, mut sink: Events) -> Vec<String> {
+        let input: &[u8] = b"a x\nb\nc\nd x\ne\nf";
+        let matcher = Substring::new("x");
+        searcher
+            .search_reader(&matcher, input, Path::new("a.txt"), &mut sink)
+            .unwrap();
+        sink.events
+    }
+
+    #[test]
+    fn events_in_order() {
+        let searcher = Searcher {
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "match 1 [(2, 3)]",
+                "context 2",
+                "context 3",
+                "match 4 [(2, 3)]",
+                "context 5",
+                "finish 2"
+            ],
+            events(searcher, Events::default())
+        );
+
+        // max_count stops after the context of the last match.
+        let searcher = Searcher {
+            max_count: Some(1),
+            after_context: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["match 1 [(2, 3)]", "context 2", "finish 1"],
+            events(searcher, Events::default())
+        );
+    }
+
+    #[test]
+    fn whole_slice() {
+        let contents = b"a x\nb\nc x\r\nd";
+        let matcher = Substring::new("x");
+        let mut sink = Events::default();
+        let searcher = Searcher::default();
+        let count = searcher
+            .search_slice(&matcher, contents, Path::new("a.txt"), &mut sink)
+            .unwrap();
+        assert_eq!(2, count);
+        assert_eq!(
+            vec!["match 1 [(2, 3)]", "match 3 [(2, 3)]", "finish 2"],
+            sink.events
+        );
+
+        // Context is found line by line, with the same events.
+        let searcher = Searcher {
+            after_context: 1,
+            ..Default::default()
+        };
+        let mut sink = Events::default();
+        searcher
+            .search_slice(&matcher, contents, Path::new("a.txt"), &mut sink)
+            .unwrap();
+        assert_eq!(
+            vec![
+                "match 1 [(2, 3)]",
+                "context 2",
+                "match 3 [(2, 3)]",
+                "context 4",
+                "finish 2"
+            ],
+            sink.events
+        );
+    }
+
+    #[test]
+    fn multiline_spans() {
+        let contents = b"a\nb x\ny\nz\nc\nd";
+        let matcher = Substring::new("x\ny\nz");
+        let searcher = Searcher {
+            multiline: true,
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        let mut sink = Events::default();
+        let count = searcher
+            .search_reader(&matcher, &contents[..], Path::new("a.txt"), &mut sink)
+            .unwrap();
+        assert_eq!(3, count);
+        assert_eq!(
+            vec![
+                "context 1",
+                "match 2 [(2, 3)]",
+                "match 3 [(0, 1)]",
+                "match 4 [(0, 1)]",
+                "context 5",
+                "finish 3"
+            ],
+            sink.events
+        );
+
+        // The literal fast path is skipped, the match holds a line break.
+        let mut sink = Events::default();
+        searcher
+            .search_slice(&matcher, b"x\ny\nz", Path::new("a.txt"), &mut sink)
+            .unwrap();
+        assert_eq!("finish 3", sink.events[3]);
+    }
+
+    #[test]
+    fn invert_and_stop() {
+        let searcher = Searcher {
+            invert: true,
+            ..Default::default()
+        };
+        let sink = Events {
+            stop_after: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["match 2 []", "match 3 []", "finish 2"],
+            events(searcher, sink)
+        );
+    }
+}
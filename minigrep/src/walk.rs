@@ -0,0 +1,298 @@
+// Recursive directory walking. Walk yields every file below a path in a
+// stable, sorted order so results come out the same on every run.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::filter::FileFilter;
+use crate::ignore::IgnoreStack;
+
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    // The root is at depth 0, so a max depth of 1 only searches its direct children.
+    pub max_depth: Option<usize>,
+    pub follow_links: bool,
+    // Hidden files and directories, names starting with '.', are skipped unless set.
+    pub hidden: bool,
+    // Set to search files excluded by .gitignore, .ignore and the global ignore file.
+    pub no_ignore: bool,
+    // The file types and globs selecting the files to search.
+    pub filter: FileFilter,
+}
+
+pub struct Walk {
+    // Paths still to visit with their depth and the ignore files that apply
+    // to their entries. The next path is at the end.
+    stack: Vec<(PathBuf, usize, Arc<IgnoreStack>)>,
+    // The path walked, which the filter globs are relative to.
+    root: PathBuf,
+    options: WalkOptions,
+    // Canonical paths of directories already read, so symlink loops end.
+    visited: HashSet<PathBuf>,
+}
+
+impl Walk {
+    pub fn new(root: impl AsRef<Path>, options: &WalkOptions) -> Walk {
+        let root = root.as_ref();
+        let ignores = if options.no_ignore {
+            IgnoreStack::default()
+        } else {
+            IgnoreStack::new(root)
+        };
+
+        Walk {
+            stack: vec![(root.to_path_buf(), 0, Arc::new(ignores))],
+            root: root.to_path_buf(),
+            options: options.clone(),
+            visited: HashSet::new(),
+        }
+    }
+
+    // Queues the children of a directory, in reverse so they pop in name order.
+    fn push_children(&mut self, dir: &Path, depth: usize, ignores: &IgnoreStack) -> io::Result<()> {
+        let ignores = if self.options.no_ignore {
+            Arc::new(ignores.clone())
+        } else {
+            Arc::new(ignores.child(dir))
+        };
+
+        let mut children = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !self.options.hidden && is_hidden(&entry.file_name()) {
+                continue;
+            }
+
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let is_dir = if file_type.is_symlink() && self.options.follow_links {
+                path.is_dir()
+            } else {
+                file_type.is_dir()
+            };
+            if !self.options.no_ignore && ignores.is_ignored(&path, is_dir) {
+                continue;
+            }
+
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if !self.options.filter.is_match(&relative, is_dir) {
+                continue;
+            }
+            children.push(path);
+        }
+
+        children.sort();
+        self.stack.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|path| (path, depth + 1, Arc::clone(&ignores))),
+        );
+        Ok(())
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, depth, ignores)) = self.stack.pop() {
+            // Paths named on the command line are always followed.
+            let metadata = if depth == 0 || self.options.follow_links {
+                fs::metadata(&path)
+            } else {
+                fs::symlink_metadata(&path)
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(err) => return Some(Err(Error::io(&path, err))),
+            };
+
+            if metadata.is_file() {
+                return Some(Ok(path));
+            }
+            if !metadata.is_dir() || self.options.max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+
+            if self.options.follow_links {
+                let canonical = match fs::canonicalize(&path) {
+                    Ok(canonical) => canonical,
+                    Err(err) => return Some(Err(Error::io(&path, err))),
+                };
+                if !self.visited.insert(canonical) {
+                    continue;
+                }
+            }
+            if let Err(err) = self.push_children(&path, depth, &ignores) {
+                return Some(Err(Error::io(&path, err)));
+            }
+        }
+
+        None
+    }
+}
+
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Types;
+
+    fn tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("minigrep-walk-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("top.txt"), "").unwrap();
+        fs::write(root.join("a/one.txt"), "").unwrap();
+        fs::write(root.join("a/b/two.txt"), "").unwrap();
+        fs::write(root.join(".git/config"), "").unwrap();
+        fs::write(root.join(".hidden"), "").unwrap();
+        root
+    }
+
+    fn walk(root: &Path, options: &WalkOptions) -> Vec<String> {
+        Walk::new(root, options)
+            .map(|path| {
+                let path = path.unwrap();
+                let relative = path.strip_prefix(root).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_sorted_and_skips_hidden() {
+        let root = tree("sorted");
+        assert_eq!(
+            vec!["a/b/two.txt", "a/one.txt", "top.txt"],
+            walk(&root, &WalkOptions::default())
+        );
+
+        let options = WalkOptions {
+            hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                ".git/config",
+                ".hidden",
+                "a/b/two.txt",
+                "a/one.txt",
+                "top.txt"
+            ],
+            walk(&root, &options)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn honors_ignore_files() {
+        let root = tree("ignore");
+        fs::write(root.join(".gitignore"), "/top.txt\nb/\n").unwrap();
+        fs::write(root.join("a/.ignore"), "!top.txt\n").unwrap();
+        fs::write(root.join("a/top.txt"), "").unwrap();
+
+        assert_eq!(
+            vec!["a/one.txt", "a/top.txt"],
+            walk(&root, &WalkOptions::default())
+        );
+
+        let options = WalkOptions {
+            no_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["a/b/two.txt", "a/one.txt", "a/top.txt", "top.txt"],
+            walk(&root, &options)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_above_root() {
+        let root = tree("above");
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.tmp\n").unwrap();
+        fs::write(root.join(".gitignore"), "b/\n/a/one.txt\n").unwrap();
+        fs::write(root.join("a/three.tmp"), "").unwrap();
+        fs::write(root.join("a/four.txt"), "").unwrap();
+
+        // Searching from below the top still reads the files up there.
+        assert_eq!(
+            vec!["four.txt"],
+            walk(&root.join("a"), &WalkOptions::default())
+        );
+        let options = WalkOptions {
+            no_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(4, walk(&root.join("a"), &options).len());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn filters() {
+        let root = tree("filter");
+        fs::write(root.join("a/lib.rs"), "").unwrap();
+        let types = Types::default();
+        let rust = [String::from("rust")];
+        let options = WalkOptions {
+            filter: FileFilter::new(&types, &rust, &[], &[]).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(vec!["a/lib.rs"], walk(&root, &options));
+
+        let globs = [String::from("*.txt"), String::from("!b")];
+        let options = WalkOptions {
+            filter: FileFilter::new(&types, &[], &[], &globs).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(vec!["a/one.txt", "top.txt"], walk(&root, &options));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn max_depth() {
+        let root = tree("depth");
+        let options = WalkOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(vec!["a/one.txt", "top.txt"], walk(&root, &options));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_end() {
+        let root = tree("links");
+        std::os::unix::fs::symlink(&root, root.join("a/loop")).unwrap();
+
+        assert_eq!(
+            vec!["a/b/two.txt", "a/one.txt", "top.txt"],
+            walk(&root, &WalkOptions::default())
+        );
+
+        let options = WalkOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        // The loop points back at the root, which has already been read.
+        assert_eq!(
+            vec!["a/b/two.txt", "a/one.txt", "top.txt"],
+            walk(&root, &options)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}